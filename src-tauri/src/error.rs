@@ -1,3 +1,6 @@
+use crate::rescue::status_word_message;
+use serde::ser::SerializeStruct;
+
 /// Custom error types for Pico Forge application.
 #[derive(Debug, thiserror::Error)]
 pub enum PFError {
@@ -7,15 +10,161 @@ pub enum PFError {
 	Io(String),
 	#[error("Device Error: {0}")]
 	Device(String),
+	#[error("Device returned status {sw1:02X}{sw2:02X}")]
+	StatusWord { sw1: u8, sw2: u8 },
+	#[error("No Smart Card Reader found.")]
+	NoReader,
+	#[error("Reader '{0}' is in use by another application.")]
+	ReaderBusy(String),
+	#[error("PIN required, verify the PIN before writing.")]
+	PinRequired,
+	/// The firmware only accepts writes over a secure channel, which isn't supported yet
+	#[error("Firmware requires a secure channel for writes, which PicoForge can't establish.")]
+	SecureChannelRequired,
+	/// `field` names the input field (camelCase, as sent by the frontend)
+	#[error("Invalid {field}: {reason}")]
+	InvalidHex { field: String, reason: String },
+	#[error("{field} {value} out of range (allowed {min}-{max})")]
+	ValueOutOfRange {
+		field: String,
+		value: u32,
+		min: u32,
+		max: u32,
+	},
+	/// `value` is not one of the ids in `accepted`
+	#[error(
+		"{field} {value} not accepted (allowed {})",
+		accepted.iter().map(u32::to_string).collect::<Vec<_>>().join(", ")
+	)]
+	NotAccepted {
+		field: String,
+		value: u32,
+		accepted: Vec<u32>,
+	},
+	#[error("{field} too long (max {max} bytes)")]
+	TooLong { field: String, max: usize },
+	/// A command ran out of time. With `started` the device may still have done what was
+	/// asked, it has to be read again to know.
+	#[error(
+		"{}",
+		if *started {
+			"Device timed out, the command may still finish. Read the device before writing again."
+		} else {
+			"Device is still busy with another command."
+		}
+	)]
+	TimedOut { started: bool },
+	/// The Rescue Applet is missing but the device answered on `applet`
+	#[error("Device answered on the {applet} applet but is not in rescue mode.")]
+	NotInRescueMode { applet: String },
+	/// Neither the Rescue Applet nor any fallback applet answered
+	#[error(
+		"Rescue Applet not found, the card may not be a Pico key (ATR {}).",
+		atr.as_deref().unwrap_or("unknown")
+	)]
+	UnknownCard { atr: Option<String> },
 }
 
-// Allow error to be serialized to string for Tauri
+// Allow error to be serialized for Tauri.
+// Status words, reader problems, invalid input and unusable cards are sent as
+// `{ type, ..fields, message }` so the frontend can branch on them, everything else is a
+// plain string.
 impl serde::Serialize for PFError {
 	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
 	where
 		S: serde::Serializer,
 	{
-		serializer.serialize_str(&self.to_string())
+		match self {
+			PFError::StatusWord { sw1, sw2 } => {
+				let mut obj = serializer.serialize_struct("PFError", 4)?;
+				obj.serialize_field("type", "statusWord")?;
+				obj.serialize_field("sw1", sw1)?;
+				obj.serialize_field("sw2", sw2)?;
+				obj.serialize_field("message", &status_word_message(*sw1, *sw2))?;
+				obj.end()
+			}
+			PFError::NoReader
+			| PFError::ReaderBusy(_)
+			| PFError::PinRequired
+			| PFError::SecureChannelRequired => {
+				let kind = match self {
+					PFError::NoReader => "noReader",
+					PFError::PinRequired => "pinRequired",
+					PFError::SecureChannelRequired => "secureChannelRequired",
+					_ => "readerBusy",
+				};
+				let mut obj = serializer.serialize_struct("PFError", 2)?;
+				obj.serialize_field("type", kind)?;
+				obj.serialize_field("message", &self.to_string())?;
+				obj.end()
+			}
+			PFError::InvalidHex { field, reason } => {
+				let mut obj = serializer.serialize_struct("PFError", 4)?;
+				obj.serialize_field("type", "invalidHex")?;
+				obj.serialize_field("field", field)?;
+				obj.serialize_field("reason", reason)?;
+				obj.serialize_field("message", &self.to_string())?;
+				obj.end()
+			}
+			PFError::ValueOutOfRange {
+				field,
+				value,
+				min,
+				max,
+			} => {
+				let mut obj = serializer.serialize_struct("PFError", 6)?;
+				obj.serialize_field("type", "valueOutOfRange")?;
+				obj.serialize_field("field", field)?;
+				obj.serialize_field("value", value)?;
+				obj.serialize_field("min", min)?;
+				obj.serialize_field("max", max)?;
+				obj.serialize_field("message", &self.to_string())?;
+				obj.end()
+			}
+			PFError::NotAccepted {
+				field,
+				value,
+				accepted,
+			} => {
+				let mut obj = serializer.serialize_struct("PFError", 5)?;
+				obj.serialize_field("type", "notAccepted")?;
+				obj.serialize_field("field", field)?;
+				obj.serialize_field("value", value)?;
+				obj.serialize_field("accepted", accepted)?;
+				obj.serialize_field("message", &self.to_string())?;
+				obj.end()
+			}
+			PFError::TooLong { field, max } => {
+				let mut obj = serializer.serialize_struct("PFError", 4)?;
+				obj.serialize_field("type", "tooLong")?;
+				obj.serialize_field("field", field)?;
+				obj.serialize_field("max", max)?;
+				obj.serialize_field("message", &self.to_string())?;
+				obj.end()
+			}
+			PFError::TimedOut { started } => {
+				let mut obj = serializer.serialize_struct("PFError", 3)?;
+				obj.serialize_field("type", "timedOut")?;
+				obj.serialize_field("started", started)?;
+				obj.serialize_field("message", &self.to_string())?;
+				obj.end()
+			}
+			PFError::NotInRescueMode { applet } => {
+				let mut obj = serializer.serialize_struct("PFError", 3)?;
+				obj.serialize_field("type", "notInRescueMode")?;
+				obj.serialize_field("applet", applet)?;
+				obj.serialize_field("message", &self.to_string())?;
+				obj.end()
+			}
+			PFError::UnknownCard { atr } => {
+				let mut obj = serializer.serialize_struct("PFError", 3)?;
+				obj.serialize_field("type", "unknownCard")?;
+				obj.serialize_field("atr", atr)?;
+				obj.serialize_field("message", &self.to_string())?;
+				obj.end()
+			}
+			_ => serializer.serialize_str(&self.to_string()),
+		}
 	}
 }
 
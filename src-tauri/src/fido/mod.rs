@@ -5,7 +5,11 @@ pub mod hid;
 
 use crate::{
 	error::PFError,
-	types::{AppConfig, DeviceInfo, FidoDeviceInfo, FullDeviceStatus, StoredCredential},
+	rescue::constants::SecureBootAlgorithm,
+	types::{
+		AppConfig, DeviceInfo, FidoDeviceInfo, FullDeviceStatus, StoredCredential,
+		flash_free_stats, format_serial,
+	},
 };
 use constants::*;
 use ctap_hid_fido2::{
@@ -258,6 +262,7 @@ pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
 		used / 1024,
 		total / 1024
 	);
+	let (flash_free_percent, low_space) = flash_free_stats(used, total);
 
 	// --- 3. Get Physical Config ---
 	log::debug!("Preparing Physical Config vendor command...");
@@ -311,13 +316,34 @@ pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
 
 	Ok(FullDeviceStatus {
 		info: DeviceInfo {
+			serial_label: format_serial(&aaguid_str),
 			serial: aaguid_str, // Using AAGUID as serial since unique serial isn't available
 			flash_used: used / 1024,
 			flash_total: total / 1024,
+			flash_free_percent,
+			low_space,
+			file_count: 0,
 			firmware_version: fw_version,
+			// CTAP reports a vendor specific integer, not a major/minor pair
+			firmware: None,
+			// The MCU/product bytes are only reported by the Rescue Applet
+			mcu: 0,
+			mcu_name: "Unknown".into(),
+			product: 0,
+			product_label: "Unknown".into(),
+			boot_count: None,
+			manufacture_date: None,
 		},
 		config,
+		config_warnings: Vec::new(),
 		secure_boot: false,
 		secure_lock: false,
+		secure_boot_algorithm: SecureBootAlgorithm::Unknown,
+		bootloader_version: None,
+		secure_lock_attempts: None,
+		enabled_curves: Vec::new(),
+		supported_curves: None,
+		pin_required: false,
+		warnings: Vec::new(),
 	})
 }
@@ -1,20 +1,599 @@
 //! Tauri Commands to interact with the pico-fido firmware via rescue and fido protocols.
-use crate::{error::PFError, fido, rescue, types::*};
+use crate::{
+	error::PFError,
+	fido, rescue,
+	rescue::{
+		LedMemory, PcscState, ProvisionControl, StagedConfig,
+		constants::{
+			BENCHMARK_ITERATION_TIMEOUT, BENCHMARK_MAX_ITERATIONS, DEVICE_TIMEOUT,
+			PROVISION_WAIT_TIMEOUT,
+		},
+	},
+	types::*,
+};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+pub const EVENT_WRITE_PROGRESS: &str = "write-progress";
+
+fn emit_write_progress(app: &AppHandle, progress: WriteProgress) {
+	if let Err(e) = app.emit(EVENT_WRITE_PROGRESS, progress) {
+		log::error!("Failed to emit {} event: {}", EVENT_WRITE_PROGRESS, e);
+	}
+}
+
+fn emit_write_finished(app: &AppHandle, result: &Result<WriteResult, PFError>) {
+	let message = match result {
+		Ok(res) => res.message.clone(),
+		Err(e) => e.to_string(),
+	};
+	emit_write_progress(
+		app,
+		WriteProgress::Finished {
+			success: result.is_ok(),
+			message,
+		},
+	);
+}
+
+/// Runs blocking PCSC work on the blocking thread pool so slow readers don't stall the
+/// async runtime. Device operations are serialised through `PcscState` so concurrent
+/// commands never interleave their APDUs.
+async fn run_rescue<T, F>(app: AppHandle, op: F) -> Result<T, PFError>
+where
+	T: Send + 'static,
+	F: FnOnce(&PcscState) -> Result<T, PFError> + Send + 'static,
+{
+	run_rescue_timeout(app, None, op).await
+}
+
+/// Like `run_rescue`, failing once `op` has run for `timeout_ms` (default `DEVICE_TIMEOUT`).
+///
+/// The timeout starts once the device lock is taken. Waiting for another command to
+/// release it is bounded by `DEVICE_TIMEOUT` on its own, a command that gives up there is
+/// never run and fails with `TimedOut { started: false }`.
+///
+/// A wedged PCSC call can't be aborted, it keeps the device lock until it returns so it
+/// can't interleave with later commands, and may still complete what it was doing. Such a
+/// command fails with `TimedOut { started: true }`, the device has to be read again to
+/// know the outcome. The cached context is dropped so later commands start on a fresh one.
+async fn run_rescue_timeout<T, F>(
+	app: AppHandle,
+	timeout_ms: Option<u64>,
+	op: F,
+) -> Result<T, PFError>
+where
+	T: Send + 'static,
+	F: FnOnce(&PcscState) -> Result<T, PFError> + Send + 'static,
+{
+	let timeout = timeout_ms
+		.map(Duration::from_millis)
+		.unwrap_or(DEVICE_TIMEOUT);
+	let (locked_tx, mut locked_rx) = tokio::sync::oneshot::channel();
+	let task = tauri::async_runtime::spawn_blocking({
+		let app = app.clone();
+		move || {
+			let state = app.state::<PcscState>();
+			let _guard = state.lock_device();
+			// Fails once the caller gave up waiting for the lock and reported the failure
+			if locked_tx.send(()).is_err() {
+				return Err(PFError::TimedOut { started: false });
+			}
+			op(&state)
+		}
+	});
+
+	if tokio::time::timeout(DEVICE_TIMEOUT, &mut locked_rx)
+		.await
+		.is_err()
+	{
+		// Closing first settles a race with the task taking the lock right now
+		locked_rx.close();
+		if locked_rx.try_recv().is_err() {
+			log::error!("Device still busy after {:?}, giving up", DEVICE_TIMEOUT);
+			return Err(PFError::TimedOut { started: false });
+		}
+	}
+
+	match tokio::time::timeout(timeout, task).await {
+		Ok(joined) => joined.map_err(|e| PFError::Device(format!("Device task failed: {}", e)))?,
+		Err(_) => {
+			log::error!("Device operation timed out after {:?}", timeout);
+			app.state::<PcscState>().invalidate();
+			Err(PFError::TimedOut { started: true })
+		}
+	}
+}
+
+#[tauri::command]
+pub fn list_readers(state: State<'_, PcscState>) -> Result<Vec<String>, PFError> {
+	rescue::list_readers(&state)
+}
 
 #[tauri::command]
-pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
-	rescue::read_device_details()
+pub async fn find_device_by_serial(
+	app: AppHandle,
+	serial: String,
+) -> Result<Option<String>, PFError> {
+	run_rescue(app, move |state| {
+		rescue::find_device_by_serial(state, &serial)
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn read_device_details(
+	app: AppHandle,
+	reader: Option<String>,
+	timeout_ms: Option<u64>,
+) -> Result<FullDeviceStatus, PFError> {
+	run_rescue_timeout(app, timeout_ms, move |state| {
+		rescue::read_device_details(state, reader.as_deref())
+	})
+	.await
 	// fido::read_device_details()
 }
 
 #[tauri::command]
-pub fn write_config(config: AppConfigInput) -> Result<String, PFError> {
-	rescue::write_config(config)
+#[allow(clippy::too_many_arguments)]
+pub async fn write_config(
+	app: AppHandle,
+	config: AppConfigInput,
+	expected_serial: Option<String>,
+	verify: Option<bool>,
+	blink_on_error: Option<bool>,
+	force: Option<bool>,
+	reader: Option<String>,
+	timeout_ms: Option<u64>,
+) -> Result<WriteResult, PFError> {
+	emit_write_progress(&app, WriteProgress::Started);
+
+	// Give the error pattern its own time budget, so a timeout can't replace the write error
+	let blink_on_error = blink_on_error.unwrap_or(false);
+	let timeout_ms = if blink_on_error {
+		Some(timeout_ms.unwrap_or(DEVICE_TIMEOUT.as_millis() as u64) * 2)
+	} else {
+		timeout_ms
+	};
+
+	// The final event carries the outcome so the UI doesn't depend on the return value.
+	// It is sent once the write returns, even if the command timed out before then, so a
+	// write that still lands after a timeout is reported as it ended.
+	let finished = Arc::new(AtomicBool::new(false));
+	let handle = app.clone();
+	let result = run_rescue_timeout(app.clone(), timeout_ms, {
+		let finished = finished.clone();
+		move |state| {
+			let progress = |sent: usize, total: usize| {
+				let fraction = sent as f32 / total as f32;
+				emit_write_progress(&handle, WriteProgress::Progress { fraction });
+			};
+			let result = rescue::write_config(
+				state,
+				config,
+				expected_serial.as_deref(),
+				verify.unwrap_or(false),
+				blink_on_error,
+				force.unwrap_or(false),
+				reader.as_deref(),
+				&progress,
+			);
+			emit_write_finished(&handle, &result);
+			finished.store(true, Ordering::Release);
+			result
+		}
+	})
+	.await;
+
+	// The write never ran (or its task panicked), nothing else will report it
+	let pending = matches!(result, Err(PFError::TimedOut { started: true }));
+	if !pending && !finished.load(Ordering::Acquire) {
+		emit_write_finished(&app, &result);
+	}
+
+	result
+}
+
+#[tauri::command]
+pub fn stage_config(
+	staged: State<'_, StagedConfig>,
+	config: AppConfigInput,
+) -> Result<AppConfigInput, PFError> {
+	rescue::stage_config(&staged, config)
+}
+
+#[tauri::command]
+pub fn discard_staged(staged: State<'_, StagedConfig>) -> bool {
+	rescue::discard_staged(&staged)
+}
+
+#[tauri::command]
+pub async fn commit_staged(app: AppHandle, reader: Option<String>) -> Result<WriteResult, PFError> {
+	let handle = app.clone();
+	run_rescue(app, move |state| {
+		let staged = handle.state::<StagedConfig>();
+		rescue::commit_staged(state, &staged, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn preview_write_config(
+	app: AppHandle,
+	config: AppConfigInput,
+	reader: Option<String>,
+) -> Result<WritePreview, PFError> {
+	run_rescue(app, move |state| {
+		rescue::preview_write_config(state, config, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn export_config(
+	app: AppHandle,
+	slot: Option<u8>,
+	reader: Option<String>,
+) -> Result<AppConfig, PFError> {
+	run_rescue(app, move |state| {
+		rescue::export_config(state, slot, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn diff_config(
+	app: AppHandle,
+	reference: AppConfig,
+	reader: Option<String>,
+) -> Result<Vec<ConfigDifference>, PFError> {
+	run_rescue(app, move |state| {
+		rescue::diff_config(state, &reference, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn preview_import(
+	app: AppHandle,
+	config: AppConfigInput,
+	reader: Option<String>,
+) -> Result<Vec<ConfigDifference>, PFError> {
+	run_rescue(app, move |state| {
+		rescue::preview_import(state, &config, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn validate_against_policy(
+	app: AppHandle,
+	policy: Policy,
+	proposed: Option<AppConfigInput>,
+	reader: Option<String>,
+) -> Result<Vec<PolicyViolation>, PFError> {
+	run_rescue(app, move |state| {
+		rescue::validate_against_policy(state, &policy, proposed.as_ref(), reader.as_deref())
+	})
+	.await
 }
 
 #[tauri::command]
-pub fn enable_secure_boot(lock: bool) -> Result<String, PFError> {
-	rescue::enable_secure_boot(lock)
+pub async fn import_config(
+	app: AppHandle,
+	config: AppConfigInput,
+	schema_version: Option<u32>,
+	reader: Option<String>,
+) -> Result<WriteResult, PFError> {
+	run_rescue(app, move |state| {
+		rescue::import_config(state, config, schema_version, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn import_config_from_file(
+	app: AppHandle,
+	path: String,
+	only: Option<Vec<String>>,
+	reader: Option<String>,
+) -> Result<WriteResult, PFError> {
+	run_rescue(app, move |state| {
+		rescue::import_config_from_file(
+			state,
+			std::path::Path::new(&path),
+			only.as_deref(),
+			reader.as_deref(),
+		)
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn export_config_toml(app: AppHandle, reader: Option<String>) -> Result<String, PFError> {
+	run_rescue(app, move |state| {
+		rescue::export_config_toml(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn import_config_toml(
+	app: AppHandle,
+	toml: String,
+	reader: Option<String>,
+) -> Result<WriteResult, PFError> {
+	run_rescue(app, move |state| {
+		rescue::import_config_toml(state, &toml, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn read_capabilities(
+	app: AppHandle,
+	reader: Option<String>,
+) -> Result<Capabilities, PFError> {
+	run_rescue(app, move |state| {
+		rescue::read_capabilities(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn read_instructions(
+	app: AppHandle,
+	reader: Option<String>,
+) -> Result<InstructionSupport, PFError> {
+	run_rescue(app, move |state| {
+		rescue::read_instructions(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn read_usb_mode(app: AppHandle, reader: Option<String>) -> Result<UsbMode, PFError> {
+	run_rescue(app, move |state| {
+		rescue::read_usb_mode(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn ping_reader(app: AppHandle, reader: Option<String>) -> Result<ReaderPing, PFError> {
+	run_rescue(app, move |state| {
+		rescue::ping_reader(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn read_telemetry(app: AppHandle, reader: Option<String>) -> Result<Telemetry, PFError> {
+	run_rescue(app, move |state| {
+		rescue::read_telemetry(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn reader_capabilities(
+	app: AppHandle,
+	reader: Option<String>,
+) -> Result<ReaderCapabilities, PFError> {
+	run_rescue(app, move |state| {
+		rescue::reader_capabilities(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn read_atr(app: AppHandle, reader: Option<String>) -> Result<AtrInfo, PFError> {
+	run_rescue(app, move |state| rescue::read_atr(state, reader.as_deref())).await
+}
+
+#[tauri::command]
+pub async fn dump_raw_phy(
+	app: AppHandle,
+	slot: Option<u8>,
+	reader: Option<String>,
+) -> Result<RawPhyDump, PFError> {
+	run_rescue(app, move |state| {
+		rescue::dump_raw_phy(state, slot, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn read_secure_boot_key_hash(
+	app: AppHandle,
+	reader: Option<String>,
+) -> Result<Option<String>, PFError> {
+	run_rescue(app, move |state| {
+		rescue::read_secure_boot_key_hash(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn list_secure_boot_keys(
+	app: AppHandle,
+	reader: Option<String>,
+) -> Result<Vec<SecureBootKeySlot>, PFError> {
+	run_rescue(app, move |state| {
+		rescue::list_secure_boot_keys(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn enable_secure_boot(
+	app: AppHandle,
+	key_index: u8,
+	lock: bool,
+	confirm_irreversible: Option<bool>,
+	reader: Option<String>,
+) -> Result<WriteResult, PFError> {
+	run_rescue(app, move |state| {
+		rescue::enable_secure_boot(
+			state,
+			key_index,
+			lock,
+			confirm_irreversible.unwrap_or(false),
+			reader.as_deref(),
+		)
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn read_secure_lock_attempts(
+	app: AppHandle,
+	reader: Option<String>,
+) -> Result<Option<u8>, PFError> {
+	run_rescue(app, move |state| {
+		rescue::read_secure_lock_attempts(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn can_unlock_secure_boot(
+	app: AppHandle,
+	reader: Option<String>,
+) -> Result<SecureBootUnlockStatus, PFError> {
+	run_rescue(app, move |state| {
+		rescue::can_unlock_secure_boot(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn identify_device(app: AppHandle, reader: Option<String>) -> Result<String, PFError> {
+	run_rescue(app, move |state| {
+		rescue::identify_device(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn set_led_enabled(
+	app: AppHandle,
+	on: bool,
+	reader: Option<String>,
+) -> Result<String, PFError> {
+	let handle = app.clone();
+	run_rescue(app, move |state| {
+		let memory = handle.state::<LedMemory>();
+		rescue::set_led_enabled(state, &memory, on, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn verify_pin(
+	app: AppHandle,
+	pin: String,
+	reader: Option<String>,
+) -> Result<String, PFError> {
+	run_rescue(app, move |state| {
+		rescue::verify_pin(state, &pin, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn set_ccid_enabled(
+	app: AppHandle,
+	on: bool,
+	reader: Option<String>,
+) -> Result<WriteResult, PFError> {
+	run_rescue(app, move |state| {
+		rescue::set_ccid_enabled(state, on, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn get_power_reset(app: AppHandle, reader: Option<String>) -> Result<bool, PFError> {
+	run_rescue(app, move |state| {
+		rescue::get_power_reset(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn set_power_reset(
+	app: AppHandle,
+	enabled: bool,
+	reader: Option<String>,
+) -> Result<String, PFError> {
+	run_rescue(app, move |state| {
+		rescue::set_power_reset(state, enabled, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn reset_vidpid(app: AppHandle, reader: Option<String>) -> Result<String, PFError> {
+	run_rescue(app, move |state| {
+		rescue::reset_vidpid(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn factory_reset(
+	app: AppHandle,
+	reader: Option<String>,
+	timeout_ms: Option<u64>,
+) -> Result<FactoryResetResult, PFError> {
+	run_rescue_timeout(app, timeout_ms, move |state| {
+		rescue::factory_reset(state, reader.as_deref())
+	})
+	.await
+}
+
+#[tauri::command]
+pub async fn self_test(
+	app: AppHandle,
+	reader: Option<String>,
+	timeout_ms: Option<u64>,
+) -> Result<SelfTestReport, PFError> {
+	run_rescue_timeout(app, timeout_ms, move |state| {
+		rescue::self_test(state, reader.as_deref())
+	})
+	.await
+}
+
+/// Debug only, see the `debug_apdu` feature.
+#[cfg(feature = "debug_apdu")]
+#[tauri::command]
+pub async fn send_apdu(
+	app: AppHandle,
+	hex: String,
+	reader: Option<String>,
+) -> Result<String, PFError> {
+	run_rescue(app, move |state| {
+		rescue::send_apdu(state, &hex, reader.as_deref())
+	})
+	.await
+}
+
+/// Debug only, see the `debug_apdu` feature.
+#[cfg(feature = "debug_apdu")]
+#[tauri::command]
+pub async fn write_raw_phy(
+	app: AppHandle,
+	hex: String,
+	force: Option<bool>,
+	reader: Option<String>,
+) -> Result<String, PFError> {
+	run_rescue(app, move |state| {
+		rescue::write_raw_phy(state, &hex, force.unwrap_or(false), reader.as_deref())
+	})
+	.await
 }
 
 #[tauri::command]
@@ -39,21 +618,104 @@ pub(crate) fn set_min_pin_length(
 	fido::set_min_pin_length(current_pin, min_pin_length)
 }
 
+/// Reboots into BOOTSEL with `to_bootsel`, otherwise back into normal (FIDO) operation.
+/// After a normal reboot wait for `device-connected` before talking to the device again.
 #[tauri::command]
-pub fn reboot(to_bootsel: bool) -> Result<String, PFError> {
-	rescue::reboot_device(to_bootsel)
+pub async fn reboot(
+	app: AppHandle,
+	to_bootsel: Option<bool>,
+	reader: Option<String>,
+) -> Result<String, PFError> {
+	run_rescue(app, move |state| {
+		rescue::reboot_device(state, to_bootsel.unwrap_or(false), reader.as_deref())
+	})
+	.await
+}
+
+/// Times repeated flash info reads to measure the reader's round trip latency.
+/// Runs under a timeout of `BENCHMARK_ITERATION_TIMEOUT` per iteration on top of the usual
+/// one, so long runs on a healthy reader aren't cut short. The first read over its budget
+/// ends the run, so a slow reader still gets a partial result within that timeout.
+#[tauri::command]
+pub async fn benchmark_reader(
+	app: AppHandle,
+	iterations: u32,
+	reader: Option<String>,
+) -> Result<ReaderBenchmark, PFError> {
+	let iterations_budget = BENCHMARK_ITERATION_TIMEOUT * iterations.min(BENCHMARK_MAX_ITERATIONS);
+	let timeout = (iterations_budget + DEVICE_TIMEOUT).as_millis() as u64;
+	run_rescue_timeout(app, Some(timeout), move |state| {
+		rescue::benchmark_reader(state, iterations, reader.as_deref())
+	})
+	.await
+}
+
+/// Waits for the device to reappear after `reboot` or a USB identity change.
+/// The usual command timeout doesn't apply, `timeout_ms` bounds the whole wait.
+/// Pass the `serial` read before the reboot to make sure the same device came back.
+#[tauri::command]
+pub async fn wait_for_device(
+	app: AppHandle,
+	timeout_ms: u32,
+	serial: Option<String>,
+	reader: Option<String>,
+) -> Result<DeviceInfo, PFError> {
+	let timeout = Duration::from_millis(timeout_ms.into());
+	// Leave room for the last connection attempt started just before the deadline
+	let overall = (timeout + DEVICE_TIMEOUT).as_millis() as u64;
+	run_rescue_timeout(app, Some(overall), move |state| {
+		rescue::wait_for_device(state, timeout, serial.as_deref(), reader.as_deref())
+	})
+	.await
+}
+
+/// Each device gets `wait_timeout_ms` (default `PROVISION_WAIT_TIMEOUT`) to be inserted
+/// plus the usual command timeout, the whole run is bounded by that per device.
+#[tauri::command]
+pub async fn provision_all(
+	app: AppHandle,
+	config: AppConfigInput,
+	count: usize,
+	wait_timeout_ms: Option<u32>,
+	reader: Option<String>,
+) -> Result<Vec<ProvisionReport>, PFError> {
+	let wait_timeout = wait_timeout_ms
+		.map(|ms| Duration::from_millis(ms.into()))
+		.unwrap_or(PROVISION_WAIT_TIMEOUT);
+	// A verified write takes a few round trips, give each device two command budgets
+	let per_device = wait_timeout + DEVICE_TIMEOUT * 2;
+	let overall = per_device.as_millis() as u64 * count.max(1) as u64;
+	let handle = app.clone();
+	run_rescue_timeout(app, Some(overall), move |state| {
+		let control = handle.state::<ProvisionControl>();
+		rescue::provision_all(
+			state,
+			&control,
+			config,
+			count,
+			wait_timeout,
+			reader.as_deref(),
+		)
+	})
+	.await
+}
+
+/// Doesn't take the device lock, so it reaches a `provision_all` run that holds it
+#[tauri::command]
+pub fn stop_provisioning(control: State<'_, ProvisionControl>) {
+	rescue::stop_provisioning(&control)
 }
 
 #[tauri::command]
 pub async fn get_credentials(pin: String) -> Result<Vec<StoredCredential>, String> {
-    tauri::async_runtime::spawn_blocking(move || {
-        fido::get_credentials(pin)
-    }).await.map_err(|e| e.to_string())?
+	tauri::async_runtime::spawn_blocking(move || fido::get_credentials(pin))
+		.await
+		.map_err(|e| e.to_string())?
 }
 
 #[tauri::command]
 pub async fn delete_credential(pin: String, credential_id: String) -> Result<String, String> {
-    tauri::async_runtime::spawn_blocking(move || {
-        fido::delete_credential(pin, credential_id)
-    }).await.map_err(|e| e.to_string())?
-}
\ No newline at end of file
+	tauri::async_runtime::spawn_blocking(move || fido::delete_credential(pin, credential_id))
+		.await
+		.map_err(|e| e.to_string())?
+}
@@ -1,11 +1,18 @@
+//! The device logic lives in `rescue` as plain functions taking a `PcscState` (or any
+//! `ApduTransport`), the Tauri commands in `io` only wrap them. Headless tools can use
+//! those modules directly without starting Tauri.
+
 use serde::Serialize;
 
-mod error;
+pub mod error;
 mod fido;
 mod io;
 mod logging;
-mod rescue;
-mod types;
+pub mod rescue;
+pub mod types;
+
+pub use error::PFError;
+pub use rescue::{PcscState, transport::ApduTransport};
 
 // This will be temporary here untill moved to a dedicated module:
 
@@ -20,20 +27,82 @@ pub fn run() {
 	logging::logger_init();
 	log::info!("Initialisng PicoForge...");
 
-	tauri::Builder::default()
+	let app = tauri::Builder::default()
 		.plugin(tauri_plugin_shell::init())
 		.plugin(tauri_plugin_opener::init())
+		.manage(rescue::PcscState::default())
+		.manage(rescue::LedMemory::default())
+		.manage(rescue::StagedConfig::default())
+		.manage(rescue::ProvisionControl::default())
 		.invoke_handler(tauri::generate_handler![
+			io::list_readers,
+			io::find_device_by_serial,
 			io::read_device_details,
 			io::write_config,
+			io::preview_write_config,
+			io::stage_config,
+			io::commit_staged,
+			io::discard_staged,
+			io::export_config,
+			io::diff_config,
+			io::preview_import,
+			io::validate_against_policy,
+			io::import_config,
+			io::import_config_from_file,
+			io::export_config_toml,
+			io::import_config_toml,
+			io::dump_raw_phy,
+			io::read_usb_mode,
+			io::read_capabilities,
+			io::read_instructions,
+			io::ping_reader,
+			io::reader_capabilities,
+			io::read_telemetry,
+			io::read_atr,
 			io::get_fido_info,
 			io::change_fido_pin,
 			io::get_credentials,
 			io::delete_credential,
 			io::set_min_pin_length,
 			io::enable_secure_boot,
-			io::reboot
+			io::can_unlock_secure_boot,
+			io::read_secure_lock_attempts,
+			io::read_secure_boot_key_hash,
+			io::list_secure_boot_keys,
+			io::factory_reset,
+			io::reset_vidpid,
+			io::set_ccid_enabled,
+			io::get_power_reset,
+			io::set_power_reset,
+			io::verify_pin,
+			io::identify_device,
+			io::set_led_enabled,
+			io::reboot,
+			io::wait_for_device,
+			io::provision_all,
+			io::stop_provisioning,
+			io::self_test,
+			io::benchmark_reader,
+			#[cfg(feature = "debug_apdu")]
+			io::send_apdu,
+			#[cfg(feature = "debug_apdu")]
+			io::write_raw_phy
 		])
-		.run(tauri::generate_context!())
-		.expect("error while running tauri application");
+		.build(tauri::generate_context!())
+		.expect("error while building tauri application");
+
+	let mut watcher = match rescue::hotplug::HotplugWatcher::start(app.handle().clone()) {
+		Ok(watcher) => Some(watcher),
+		Err(e) => {
+			log::error!("Failed to start hotplug watcher: {}", e);
+			None
+		}
+	};
+
+	app.run(move |_app, event| {
+		if let tauri::RunEvent::Exit = event {
+			// Dropping the watcher stops and joins its thread
+			drop(watcher.take());
+		}
+	});
 }
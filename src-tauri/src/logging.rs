@@ -64,10 +64,32 @@ pub fn logger_init() {
         (LevelFilter::Info, LevelFilter::Error)
     };
 
+    // RUST_LOG overrides the application log level, and can set single modules too, e.g.
+    // `info,picoforge_lib::rescue=debug`. APDU headers and status words are logged at
+    // debug, full payloads only at trace.
+    let (rust_log_level, module_levels) = std::env::var("RUST_LOG")
+        .map(|spec| parse_rust_log(&spec))
+        .unwrap_or_default();
+    let app_level = rust_log_level.unwrap_or(app_level);
+
+    // Module paths of the binary and the library crate, where almost all logging happens
+    let mut loggers = vec![
+        ("picoforge".to_owned(), app_level),
+        ("picoforge_lib".to_owned(), app_level),
+    ];
+    for (module, level) in module_levels {
+        loggers.retain(|(name, _)| *name != module);
+        loggers.push((module, level));
+    }
+
     let config = log4rs::Config::builder()
         .appender(Appender::builder().build("stdout", Box::new(stdout)))
         .appender(Appender::builder().build("logfile", Box::new(logfile)))
-        .logger(Logger::builder().build("picoforge", app_level))
+        .loggers(
+            loggers
+                .into_iter()
+                .map(|(name, level)| Logger::builder().build(name, level)),
+        )
         .build(
             Root::builder()
                 .appenders(vec!["logfile", "stdout"])
@@ -77,3 +99,28 @@ pub fn logger_init() {
 
     log4rs::init_config(config).unwrap();
 }
+
+/// Splits a `RUST_LOG` value into the default level and per module levels.
+///
+/// Supports the comma separated `level`, `module=level` and bare `module` (meaning trace)
+/// directives of env_logger, but not its `/regex` message filter. Directives that don't
+/// parse are reported on stderr and skipped, since the logger isn't running yet.
+fn parse_rust_log(spec: &str) -> (Option<LevelFilter>, Vec<(String, LevelFilter)>) {
+    let mut default = None;
+    let mut modules = Vec::new();
+
+    for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        match directive.split_once('=') {
+            Some((module, level)) => match level.trim().parse() {
+                Ok(level) => modules.push((module.trim().to_owned(), level)),
+                Err(_) => eprintln!("Ignoring RUST_LOG directive '{}': unknown level", directive),
+            },
+            None => match directive.parse() {
+                Ok(level) => default = Some(level),
+                Err(_) => modules.push((directive.to_owned(), LevelFilter::Trace)),
+            },
+        }
+    }
+
+    (default, modules)
+}
@@ -1,6 +1,7 @@
 //! Constants, enums, bitflags and data structures for Rescue Application for pico-fido firmware.
 
-// use serde::{Deserialize, Serialize};
+use crate::types::FirmwareVersion;
+use serde::{Deserialize, Serialize};
 // use std::fmt;
 
 // --- 1. ISO 7816-4 Standard Constants ---
@@ -9,21 +10,199 @@
 pub const APDU_CLA_ISO: u8 = 0x00; // Standard ISO commands
 pub const APDU_CLA_PROPRIETARY: u8 = 0x80; // Custom/Rescue commands
 
+/// CLA bit signalling that more command chunks follow (ISO 7816-4 command chaining)
+pub const APDU_CLA_CHAINING: u8 = 0x10;
+
+/// Maximum data length (Lc) of a short APDU
+pub const APDU_SHORT_MAX_DATA: usize = 255;
+
 /// Instruction (INS) for Selection
 pub const APDU_INS_SELECT: u8 = 0xA4;
 
+/// Instruction (INS) to fetch remaining response data after a `61XX` status
+pub const APDU_INS_GET_RESPONSE: u8 = 0xC0;
+
+/// Instruction (INS) to verify a PIN, for firmware whose Rescue Applet is PIN protected
+pub const APDU_INS_VERIFY: u8 = 0x20;
+
+/// P2 of VERIFY, the reference of the rescue PIN
+pub const APDU_P2_RESCUE_PIN: u8 = 0x80;
+
 /// Selection Parameters (P1, P2)
 pub const APDU_P1_SELECT_BY_DF_NAME: u8 = 0x04;
 pub const APDU_P2_RETURN_FCI: u8 = 0x04; // Return File Control Info
 
 /// Status Words (SW1 SW2)
 pub const SW_SUCCESS: [u8; 2] = [0x90, 0x00];
+pub const SW1_MORE_DATA: u8 = 0x61; // SW2 holds the number of bytes still available
+pub const SW1_PIN_TRIES: u8 = 0x63; // Verification failed, SW2 is Cx with x tries left
+pub const SW_SELECTED_FILE_DEACTIVATED: [u8; 2] = [0x62, 0x83];
+pub const SW_SECURITY_NOT_SATISFIED: [u8; 2] = [0x69, 0x82];
+pub const SW_SM_OBJECTS_MISSING: [u8; 2] = [0x69, 0x87]; // Secure messaging expected
+pub const SW_SM_OBJECTS_INCORRECT: [u8; 2] = [0x69, 0x88];
+pub const SW_WRONG_P1P2: [u8; 2] = [0x6A, 0x86];
+pub const SW_INS_NOT_SUPPORTED: [u8; 2] = [0x6D, 0x00];
+pub const SW_NOT_FOUND: [u8; 2] = [0x6A, 0x88];
 
 // --- 2. Rescue Applet Constants ---
 
 // The Rescue Application ID (AID) from src/rescue.c
 pub const RESCUE_AID: &[u8] = &[0xA0, 0x58, 0x3F, 0xC1, 0x9B, 0x7E, 0x4F, 0x21];
 
+/// Other applets a pico-keys device may answer on when the Rescue Applet isn't selectable,
+/// tried in order. Only used to recognise the device and point the user to rescue mode.
+pub const FALLBACK_AIDS: &[(&str, &[u8])] = &[
+	(
+		"Management",
+		&[0xA0, 0x00, 0x00, 0x05, 0x27, 0x47, 0x11, 0x17],
+	),
+	("FIDO", &[0xA0, 0x00, 0x00, 0x06, 0x47, 0x2F, 0x00, 0x01]),
+];
+
+/// MCU identifier reported in byte 0 of the select response (`PICO_MCU`)
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PicoMcu {
+	Rp2040 = 0x01,
+	Rp2350 = 0x02,
+	Esp32 = 0x03,
+}
+
+impl PicoMcu {
+	pub fn from_u8(val: u8) -> Option<Self> {
+		match val {
+			0x01 => Some(Self::Rp2040),
+			0x02 => Some(Self::Rp2350),
+			0x03 => Some(Self::Esp32),
+			_ => None,
+		}
+	}
+
+	/// Human-readable name, falling back to the raw hex for unknown values
+	pub fn describe(val: u8) -> String {
+		match Self::from_u8(val) {
+			Some(Self::Rp2040) => "RP2040".into(),
+			Some(Self::Rp2350) => "RP2350".into(),
+			Some(Self::Esp32) => "ESP32".into(),
+			None => format!("Unknown (0x{:02X})", val),
+		}
+	}
+
+	/// GPIO pins that can drive the LED on `mcu`, `LED_GPIO_VALID` for an unknown MCU
+	pub fn led_gpio_range(mcu: u8) -> std::ops::RangeInclusive<u8> {
+		match Self::from_u8(mcu) {
+			Some(Self::Rp2040) => 0..=29,
+			// RP2350B, the RP2350A package only bonds out GPIO 0-29
+			Some(Self::Rp2350) => 0..=47,
+			// ESP32-S3
+			Some(Self::Esp32) => 0..=48,
+			None => LED_GPIO_VALID,
+		}
+	}
+}
+
+/// Product identifier reported in byte 1 of the select response (`PICO_PRODUCT`)
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PicoProduct {
+	Hsm = 0x01,
+	Fido = 0x02,
+	OpenPgp = 0x03,
+}
+
+impl PicoProduct {
+	pub fn from_u8(val: u8) -> Option<Self> {
+		match val {
+			0x01 => Some(Self::Hsm),
+			0x02 => Some(Self::Fido),
+			0x03 => Some(Self::OpenPgp),
+			_ => None,
+		}
+	}
+
+	/// Human-readable name, falling back to the raw hex for unknown values
+	pub fn describe(val: u8) -> String {
+		match Self::from_u8(val) {
+			Some(Self::Hsm) => "Pico HSM".into(),
+			Some(Self::Fido) => "Pico FIDO".into(),
+			Some(Self::OpenPgp) => "Pico OpenPGP".into(),
+			None => format!("Unknown (0x{:02X})", val),
+		}
+	}
+}
+
+/// Signature algorithm the boot ROM uses to verify a secure boot key.
+///
+/// The secure boot status response carries no algorithm byte, the algorithm is fixed by
+/// the MCU's boot ROM (the RP2350 only verifies secp256k1 ECDSA signatures).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SecureBootAlgorithm {
+	EcdsaSecp256k1,
+	Unknown,
+}
+
+impl SecureBootAlgorithm {
+	pub fn for_mcu(mcu: u8) -> Self {
+		match PicoMcu::from_u8(mcu) {
+			Some(PicoMcu::Rp2350) => Self::EcdsaSecp256k1,
+			_ => Self::Unknown,
+		}
+	}
+}
+
+/// LED driver id stored in `TAG_LED_DRIVER` (`PHY_LED_DRIVER_*`).
+///
+/// Serialized as the raw numeric id, ids this app doesn't know about are kept in `Unknown`
+/// so they survive a read/write round trip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "u8", into = "u8")]
+pub enum LedDriver {
+	Pico,
+	Pimoroni,
+	Ws2812,
+	Cyw43,
+	Neopixel,
+	Unknown(u8),
+}
+
+impl LedDriver {
+	/// Every driver id this app knows about and `write_config` accepts, see `From<u8>`
+	pub const KNOWN: &[Self] = &[
+		Self::Pico,
+		Self::Pimoroni,
+		Self::Ws2812,
+		Self::Cyw43,
+		Self::Neopixel,
+	];
+}
+
+impl From<u8> for LedDriver {
+	fn from(val: u8) -> Self {
+		match val {
+			0x01 => Self::Pico,
+			0x02 => Self::Pimoroni,
+			0x03 => Self::Ws2812,
+			0x04 => Self::Cyw43,
+			0x05 => Self::Neopixel,
+			other => Self::Unknown(other),
+		}
+	}
+}
+
+impl From<LedDriver> for u8 {
+	fn from(driver: LedDriver) -> Self {
+		match driver {
+			LedDriver::Pico => 0x01,
+			LedDriver::Pimoroni => 0x02,
+			LedDriver::Ws2812 => 0x03,
+			LedDriver::Cyw43 => 0x04,
+			LedDriver::Neopixel => 0x05,
+			LedDriver::Unknown(val) => val,
+		}
+	}
+}
+
 // APDU Instructions
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -35,7 +214,27 @@ pub enum RescueInstruction {
 	Reboot = 0x1F,
 }
 
+/// Instructions `read_instructions` probes, with a P1 the applet rejects before acting.
+/// None of them has a handler for P1 0x00, so a known instruction answers `6A86` while an
+/// unknown one answers `6D00`. Read is harmless with any parameter.
+///
+/// NOTE: Secure and Reboot are never probed. Their parameters are a key index / lock flag
+/// and a reboot mode the firmware may act on for any value.
+pub const INSTRUCTION_PROBES: &[(RescueInstruction, u8)] = &[
+	(RescueInstruction::KeyDevSign, 0x00),
+	(RescueInstruction::Write, 0x00),
+	(RescueInstruction::Read, ReadParam::PhyConfig as u8),
+];
+
+/// Instructions `read_instructions` reports without probing, see `INSTRUCTION_PROBES`
+pub const UNPROBED_INSTRUCTIONS: &[RescueInstruction] =
+	&[RescueInstruction::Secure, RescueInstruction::Reboot];
+
 /// P1 Parameters for RescueInstruction::Read (0x1E)
+///
+/// NOTE: The applet has no directory listing read, only the aggregate file count from
+/// `FlashInfo` is available. There is no read of a single file by id either, flash files
+/// are only reachable through the applet that owns them (e.g. FIDO credentials).
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ReadParam {
@@ -77,12 +276,114 @@ pub enum SecureLockParam {
 	Lock = 0x01,
 }
 
+/// Longest PHY value whose length fits the single byte short form, `81`/`82` start a
+/// long form length
+pub const TLV_SHORT_LENGTH_MAX: u8 = 0x7F;
+
+/// Size of the `usb_product` buffer in `phy_data_t` (including the null terminator)
+pub const USB_PRODUCT_MAX_LEN: usize = 32;
+
+/// GPIO pins that can drive the LED on any supported MCU, checked before the device is
+/// known. The connected MCU's own range is checked by `PicoMcu::led_gpio_range`.
+pub const LED_GPIO_VALID: std::ops::RangeInclusive<u8> = 0..=48;
+
+/// Board labels accepted for `led_pin_label` besides `GPn`/`GPIOn`
+pub const LED_PIN_LABELS: &[(&str, u8)] = &[
+	("LED", 25), // Onboard LED of the Raspberry Pi Pico
+	("ONBOARD", 25),
+];
+
+/// Highest LED brightness level accepted by the firmware
+pub const LED_BRIGHTNESS_MAX: u8 = 15;
+
+/// `TAG_PRESENCE_TIMEOUT` value that turns the user presence check off.
+/// Any other value is the timeout in whole seconds.
+pub const TOUCH_TIMEOUT_DISABLED: u8 = 0;
+
+/// Initial buffer size for listing reader names, grown on demand
+pub const READERS_BUF_LEN: usize = 2048;
+
+/// Number of on/off cycles used by `identify_device`
+pub const IDENTIFY_BLINK_COUNT: usize = 3;
+
+/// Time the LED stays in each state while identifying
+pub const IDENTIFY_BLINK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(300);
+
+/// Number of on/off cycles signalling a failed write, see `write_config`
+pub const ERROR_BLINK_COUNT: usize = 2;
+
+/// Time the LED stays in each state while signalling a failed write
+pub const ERROR_BLINK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(80);
+
+/// How often a read is retried after the card was reset or removed mid transaction
+pub const CARD_RESET_RETRIES: u32 = 3;
+
+/// Base delay before reconnecting after a card reset, multiplied by the attempt number
+pub const CARD_RESET_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Most iterations a single `benchmark_reader` run may do
+pub const BENCHMARK_MAX_ITERATIONS: u32 = 1000;
+
+/// A `benchmark_reader` round trip slower than this counts as failed
+pub const BENCHMARK_ITERATION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// How long a rescue command may take before it is reported as timed out
+pub const DEVICE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Longest `wait_for_device` blocks on a PCSC status change before trying to connect again.
+/// Keeps it polling on platforms that never report reader changes.
+pub const WAIT_FOR_DEVICE_POLL_INTERVAL: std::time::Duration =
+	std::time::Duration::from_millis(500);
+
+/// Default time `provision_all` waits for the next device before ending the run
+pub const PROVISION_WAIT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+
+/// Field offsets in the data of a Rescue Applet select response, before the status word:
+/// `[MCU, Product, Major, Minor, Serial(8)]`
+pub const SELECT_MCU: std::ops::Range<usize> = 0..1;
+pub const SELECT_PRODUCT: std::ops::Range<usize> = 1..2;
+pub const SELECT_VERSION: std::ops::Range<usize> = 2..4;
+/// Missing on firmware that predates serial reporting
+pub const SELECT_SERIAL: std::ops::Range<usize> = 4..12;
+/// `DeviceInfo::serial` reported for firmware that doesn't send a serial
+pub const PLACEHOLDER_SERIAL: &str = "00000000";
+
+/// Free flash percentage below which `DeviceInfo::low_space` is set
+pub const LOW_FLASH_FREE_PERCENT: u8 = 10;
+
+/// Number of boot key slots in OTP, selected via P1 of RescueInstruction::Secure (0 = default)
+///
+/// NOTE: The applet has no command to load a key into a slot. Secure only enables secure
+/// boot with the key already in the slot, the data field doesn't carry a key.
+pub const SECURE_BOOT_KEY_SLOTS: u8 = 4;
+
+/// PHY config slot read when none is given, selected via P2 of a PHY config read.
+/// Firmware with backup config partitions exposes them as further slots.
+pub const PHY_SLOT_ACTIVE: u8 = 0x01;
+
+/// Length of the boot key hash (SHA-256) in a secure boot read
+pub const SECURE_BOOT_KEY_HASH_LEN: usize = 32;
+
+/// Oldest firmware `(major, minor)` whose Rescue Applet accepts the PHY writes sent by
+/// this tool (TLV config, chained writes, secure boot)
+pub const MIN_WRITE_FIRMWARE_VERSION: FirmwareVersion = FirmwareVersion::new(6, 0);
+
+/// VID/PID pico-keys firmware ships with (`USB_VID` / `USB_PID` in pico-keys-sdk)
+pub const FACTORY_USB_VID: u16 = 0xFEFF;
+pub const FACTORY_USB_PID: u16 = 0xFCFD;
+
 /// Default P2 value when not used
 pub const P2_UNUSED: u8 = 0x00;
 
 // --- 3. PHY Configuration Tags & Flags ---
 
 // PHY Tags from src/fs/phy.h
+//
+// NOTE: phy.h has no LED gamma/brightness curve tag, the brightness is the single
+// `TAG_LED_BTNESS` byte and the firmware applies it as is. A `led_gamma` config field needs
+// a tag number from the firmware first, guessing one could collide with a future tag.
+// There is no USB manufacturer string tag either, only `TAG_USB_PRODUCT`; the firmware
+// reports a fixed manufacturer.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PhyTag {
@@ -93,6 +394,7 @@ pub enum PhyTag {
 	PresenceTimeout = 0x08, // Previously TAG_UP_BTN
 	UsbProduct = 0x09,
 	Curves = 0x0A,
+	UsbInterfaces = 0x0B, // TAG_ENABLED_USB_ITF
 	LedDriver = 0x0C,
 }
 
@@ -107,14 +409,30 @@ impl PhyTag {
 			0x08 => Some(Self::PresenceTimeout),
 			0x09 => Some(Self::UsbProduct),
 			0x0A => Some(Self::Curves),
+			0x0B => Some(Self::UsbInterfaces),
 			0x0C => Some(Self::LedDriver),
 			_ => None,
 		}
 	}
+
+	/// Minimum value length the parser needs to decode this tag
+	pub fn min_len(&self) -> usize {
+		match self {
+			Self::VidPid | Self::Curves => 4,
+			Self::Opts => 2,
+			Self::LedGpio
+			| Self::LedBrightness
+			| Self::PresenceTimeout
+			| Self::UsbInterfaces
+			| Self::LedDriver => 1,
+			Self::UsbProduct => 0,
+		}
+	}
 }
 
 bitflags::bitflags! {
 	/// Configuration options for TAG_OPTS (Tag 0x06)
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 	pub struct RescueOptions: u16 {
 		const LED_DIMMABLE = 0x02;
 		const DISABLE_POWER_RESET = 0x04;
@@ -122,9 +440,49 @@ bitflags::bitflags! {
 	}
 }
 
+bitflags::bitflags! {
+	/// Enabled USB interfaces for TAG_ENABLED_USB_ITF (Tag 0x0B)
+	pub struct UsbInterfaces: u8 {
+		const CCID = 0x01;
+		const WCID = 0x02;
+		const HID = 0x04;
+		const KEYBOARD = 0x08;
+	}
+}
+
 bitflags::bitflags! {
 	/// Enabled curves for TAG_CURVES (Tag 0x0A)
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 	pub struct RescueCurves: u32 {
+		const SECP256R1 = 0x01;
+		const SECP384R1 = 0x02;
+		const SECP521R1 = 0x04;
 		const SECP256K1 = 0x08;
+		const BP256R1 = 0x10;
+		const BP384R1 = 0x20;
+		const BP512R1 = 0x40;
+		const ED25519 = 0x80;
+		const ED448 = 0x100;
+	}
+}
+
+impl RescueOptions {
+	/// Whether the device power cycles on reset. The firmware stores the inverse, a set
+	/// `DISABLE_POWER_RESET` bit means no power cycle.
+	pub fn power_reset(self) -> bool {
+		!self.contains(Self::DISABLE_POWER_RESET)
+	}
+
+	pub fn set_power_reset(&mut self, enabled: bool) {
+		self.set(Self::DISABLE_POWER_RESET, !enabled);
+	}
+}
+
+impl RescueCurves {
+	/// Lowercase names of the known curves in this mask, e.g. `secp256r1`
+	pub fn names(self) -> Vec<String> {
+		self.iter_names()
+			.map(|(name, _)| name.to_ascii_lowercase())
+			.collect()
 	}
 }
@@ -0,0 +1,229 @@
+//! Background watcher that reports smart card insertion/removal to the frontend.
+//!
+//! Emits `device-connected` / `device-removed` Tauri events carrying the reader name.
+//!
+//! The watcher runs on its own PCSC context and only ever waits for status changes, it
+//! never connects to a card. Commands therefore can't hit sharing violations or wait on
+//! the watcher, and its blocking wait can be cancelled without touching their context.
+//! When the PCSC service goes away (e.g. pcscd is restarted) the watcher establishes a
+//! new context, retrying with a growing delay until the service is back.
+
+use super::{is_service_error, list_reader_cstrings};
+use crate::error::PFError;
+use pcsc::{Context, PNP_NOTIFICATION, ReaderState, Scope, State};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+
+pub const EVENT_DEVICE_CONNECTED: &str = "device-connected";
+pub const EVENT_DEVICE_REMOVED: &str = "device-removed";
+
+/// How long a single status change wait blocks before re-listing readers.
+/// Keeps new readers showing up on platforms without PnP notifications.
+const STATUS_CHANGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Delay before retrying after an unexpected PCSC error
+const ERROR_BACKOFF: Duration = Duration::from_secs(2);
+
+/// First and longest delay between attempts to reach a stopped PCSC service
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// How often a backoff sleep checks whether the watcher was stopped
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Handle to the watcher thread. The thread is stopped and joined on `stop()` or drop.
+pub struct HotplugWatcher {
+	/// Context the thread currently waits on, replaced when it reconnects
+	ctx: Arc<Mutex<Context>>,
+	stop: Arc<AtomicBool>,
+	handle: Option<JoinHandle<()>>,
+}
+
+impl HotplugWatcher {
+	pub fn start(app: AppHandle) -> Result<Self, PFError> {
+		let ctx = Arc::new(Mutex::new(Context::establish(Scope::User)?));
+		let stop = Arc::new(AtomicBool::new(false));
+
+		let handle = std::thread::Builder::new()
+			.name("pcsc-hotplug".into())
+			.spawn({
+				let ctx = ctx.clone();
+				let stop = stop.clone();
+				move || watch(app, ctx, stop)
+			})
+			.map_err(|e| PFError::Io(format!("Failed to spawn hotplug thread: {}", e)))?;
+
+		log::info!("Hotplug watcher started");
+		Ok(Self {
+			ctx,
+			stop,
+			handle: Some(handle),
+		})
+	}
+
+	/// Signals the watcher thread to exit, interrupts any pending wait and joins it
+	pub fn stop(&mut self) {
+		let Some(handle) = self.handle.take() else {
+			return;
+		};
+
+		self.stop.store(true, Ordering::Relaxed);
+		let ctx = self.ctx.lock().unwrap_or_else(|e| e.into_inner());
+		if let Err(e) = ctx.cancel() {
+			log::warn!("Failed to cancel hotplug status wait: {}", e);
+		}
+		// Released before joining, a reconnecting thread needs it to finish
+		drop(ctx);
+		if handle.join().is_err() {
+			log::error!("Hotplug watcher thread panicked");
+		}
+		log::info!("Hotplug watcher stopped");
+	}
+}
+
+impl Drop for HotplugWatcher {
+	fn drop(&mut self) {
+		self.stop();
+	}
+}
+
+fn watch(app: AppHandle, shared: Arc<Mutex<Context>>, stop: Arc<AtomicBool>) {
+	let mut ctx = shared.lock().unwrap_or_else(|e| e.into_inner()).clone();
+	let mut states = vec![ReaderState::new(PNP_NOTIFICATION(), State::UNAWARE)];
+	// Last reported card presence per reader
+	let mut present: HashMap<CString, bool> = HashMap::new();
+
+	while !stop.load(Ordering::Relaxed) {
+		// Drop readers that disappeared, their removal was reported in the previous round
+		states.retain(|rs| !is_dead(rs));
+		present.retain(|name, _| states.iter().any(|rs| rs.name() == name.as_c_str()));
+
+		// Pick up newly attached readers
+		match list_reader_cstrings(&ctx) {
+			Ok(names) => {
+				for name in names {
+					if !states.iter().any(|rs| rs.name() == name.as_c_str()) {
+						log::debug!("Watching new reader: {:?}", name);
+						states.push(ReaderState::new(name, State::UNAWARE));
+					}
+				}
+			}
+			Err(pcsc::Error::NoReadersAvailable) => {}
+			Err(e) if is_service_error(&e) => {
+				let Some(new) = reconnect(&shared, &stop, e) else {
+					break;
+				};
+				ctx = new;
+				forget_states(&mut states);
+				continue;
+			}
+			Err(e) => {
+				log::warn!("Hotplug watcher failed to list readers: {}", e);
+				sleep_unless_stopped(&stop, ERROR_BACKOFF);
+				continue;
+			}
+		}
+
+		for rs in &mut states {
+			rs.sync_current_state();
+		}
+
+		match ctx.get_status_change(Some(STATUS_CHANGE_TIMEOUT), &mut states) {
+			Ok(()) => {}
+			Err(pcsc::Error::Timeout) => continue,
+			Err(pcsc::Error::Cancelled) => break,
+			Err(e) if is_service_error(&e) => {
+				let Some(new) = reconnect(&shared, &stop, e) else {
+					break;
+				};
+				ctx = new;
+				forget_states(&mut states);
+				continue;
+			}
+			Err(e) => {
+				log::warn!("Hotplug watcher status change failed: {}", e);
+				sleep_unless_stopped(&stop, ERROR_BACKOFF);
+				continue;
+			}
+		}
+
+		for rs in &states {
+			if rs.name() == PNP_NOTIFICATION() {
+				continue;
+			}
+
+			let now = !is_dead(rs) && rs.event_state().contains(State::PRESENT);
+			let was = present.insert(rs.name().to_owned(), now).unwrap_or(false);
+			if now == was {
+				continue;
+			}
+
+			let reader = rs.name().to_string_lossy().into_owned();
+			let event = if now {
+				EVENT_DEVICE_CONNECTED
+			} else {
+				EVENT_DEVICE_REMOVED
+			};
+			log::info!("Hotplug: {} on reader '{}'", event, reader);
+			if let Err(e) = app.emit(event, reader) {
+				log::error!("Failed to emit {} event: {}", event, e);
+			}
+		}
+	}
+}
+
+/// Establishes a new context after `err` said the PCSC service went away, waiting longer
+/// after every failed attempt. The new context is shared so `stop` cancels waits on it.
+/// Returns `None` if the watcher is stopped first.
+fn reconnect(shared: &Mutex<Context>, stop: &AtomicBool, err: pcsc::Error) -> Option<Context> {
+	log::warn!("Hotplug watcher lost the PCSC service: {}", err);
+	let mut delay = RECONNECT_BACKOFF_MIN;
+	while !stop.load(Ordering::Relaxed) {
+		match Context::establish(Scope::User) {
+			Ok(ctx) => {
+				log::info!("Hotplug watcher reconnected to the PCSC service");
+				*shared.lock().unwrap_or_else(|e| e.into_inner()) = ctx.clone();
+				return Some(ctx);
+			}
+			Err(e) => {
+				log::debug!(
+					"PCSC service still unavailable ({}), retrying in {:?}",
+					e,
+					delay
+				);
+				sleep_unless_stopped(stop, delay);
+				delay = (delay * 2).min(RECONNECT_BACKOFF_MAX);
+			}
+		}
+	}
+	None
+}
+
+/// Resets every reader to `UNAWARE`, the states of the old context mean nothing to a new
+/// one. Readers that are gone come back dead on the next wait and their removal is
+/// reported as usual.
+fn forget_states(states: &mut [ReaderState]) {
+	for rs in states {
+		*rs = ReaderState::new(rs.name().to_owned(), State::UNAWARE);
+	}
+}
+
+fn sleep_unless_stopped(stop: &AtomicBool, duration: Duration) {
+	let deadline = Instant::now() + duration;
+	while !stop.load(Ordering::Relaxed) {
+		let left = deadline.saturating_duration_since(Instant::now());
+		if left.is_zero() {
+			return;
+		}
+		std::thread::sleep(left.min(STOP_POLL_INTERVAL));
+	}
+}
+
+fn is_dead(rs: &ReaderState) -> bool {
+	rs.event_state().intersects(State::UNKNOWN | State::IGNORE)
+}
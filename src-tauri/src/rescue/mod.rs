@@ -3,370 +3,3191 @@
 //! For more details checkout the [pico-key-sdk](https://github.com/polhenarejos/pico-keys-sdk/blob/main/src/rescue.c)
 
 pub mod constants;
+pub mod hotplug;
+pub mod transport;
+
+#[cfg(test)]
+mod tests;
 
 use crate::{error::PFError, rescue::constants::*, types::*};
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{BigEndian, ReadBytesExt};
 use log;
-use pcsc::{Context, Protocols, Scope, ShareMode};
+use pcsc::{
+	Context, Disposition, PNP_NOTIFICATION, Protocols, ReaderState, Scope, ShareMode, State,
+};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString};
 use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use transport::{ApduTransport, ConnectedCard};
+
+/// PCSC context shared by all commands, registered as Tauri managed state.
+///
+/// The context is established lazily so the app still starts when the PCSC service
+/// isn't running yet, and is re-established whenever it becomes invalid.
+///
+/// Only command transactions use this context, serialised by `lock_device`. Status change
+/// monitoring (`hotplug`, `wait_for_device`) always runs on a separate context.
+#[derive(Default)]
+pub struct PcscState {
+	ctx: Mutex<Option<Context>>,
+	/// Held for the duration of a device operation
+	device: Mutex<()>,
+	/// Rescue PIN accepted by `verify_pin`, re-sent on every write connection
+	pin: Mutex<Option<String>>,
+}
+
+impl PcscState {
+	/// Serialises device operations, hold the guard for as long as the card is in use
+	pub fn lock_device(&self) -> MutexGuard<'_, ()> {
+		self.device.lock().unwrap_or_else(|e| e.into_inner())
+	}
+
+	/// Returns the cached context, establishing a new one if there is none or it went stale
+	fn context(&self) -> Result<Context, PFError> {
+		let mut guard = self.ctx.lock().unwrap_or_else(|e| e.into_inner());
+
+		if let Some(ctx) = guard.as_ref() {
+			if ctx.is_valid().is_ok() {
+				return Ok(ctx.clone());
+			}
+			log::warn!("Cached PCSC context is no longer valid, re-establishing");
+		}
+
+		let ctx = Context::establish(Scope::User)?;
+		*guard = Some(ctx.clone());
+		Ok(ctx)
+	}
+
+	/// Drops the cached context so the next call re-establishes it
+	pub fn invalidate(&self) {
+		*self.ctx.lock().unwrap_or_else(|e| e.into_inner()) = None;
+	}
+}
+
+/// Returns `true` for errors that indicate the PCSC context itself is unusable
+fn is_stale_context(err: &PFError) -> bool {
+	matches!(err, PFError::Pcsc(e) if is_service_error(e))
+}
+
+/// PCSC errors after which the context has to be established again, e.g. because the
+/// service was restarted
+pub(crate) fn is_service_error(err: &pcsc::Error) -> bool {
+	matches!(
+		err,
+		pcsc::Error::NoService | pcsc::Error::ServiceStopped | pcsc::Error::InvalidHandle
+	)
+}
+
+/// Decodes an ISO 7816-4 status word into a human readable message
+pub fn status_word_message(sw1: u8, sw2: u8) -> String {
+	let msg = match (sw1, sw2) {
+		(0x90, 0x00) => "Success",
+		(0x67, 0x00) => "Wrong length",
+		(0x69, 0x82) => "Security status not satisfied",
+		(0x69, 0x85) => "Conditions of use not satisfied",
+		(0x69, 0x87) => "Secure messaging data objects missing",
+		(0x69, 0x88) => "Secure messaging data objects incorrect",
+		(0x6A, 0x82) => "File or application not found",
+		(0x6A, 0x86) => "Incorrect parameters P1-P2",
+		(0x6D, 0x00) => "Instruction not supported",
+		(0x6E, 0x00) => "Class not supported",
+		_ => "Unknown status",
+	};
+	format!("{} (SW {:02X}{:02X})", msg, sw1, sw2)
+}
+
+/// Returns `Ok` if the response ends with `90 00`, otherwise a `PFError::StatusWord`.
+/// `context` is only used for logging the decoded failure.
+fn check_status(rx: &[u8], context: &str) -> Result<(), PFError> {
+	if rx.ends_with(&SW_SUCCESS) {
+		return Ok(());
+	}
+
+	match rx {
+		[.., sw1, sw2] => {
+			log::error!("{}: {}", context, status_word_message(*sw1, *sw2));
+			Err(PFError::StatusWord {
+				sw1: *sw1,
+				sw2: *sw2,
+			})
+		}
+		_ => {
+			log::error!("{}: Empty response from device", context);
+			Err(PFError::Device(format!(
+				"{}: Empty response from device",
+				context
+			)))
+		}
+	}
+}
+
+/// Logs an outgoing APDU. Only the header and length are logged at debug level, the data
+/// field goes to trace since it can carry user values such as the product name.
+fn log_apdu(apdu: &[u8]) {
+	let (header, data) = apdu.split_at(apdu.len().min(5));
+	log::debug!(
+		">> {} (+{} data bytes)",
+		hex::encode_upper(header),
+		data.len()
+	);
+	// Never log a PIN, not even at trace level
+	if header.get(1) == Some(&APDU_INS_VERIFY) {
+		return;
+	}
+	log::trace!(">> {}", hex::encode_upper(apdu));
+}
+
+/// Logs a response, status word at debug level and the data (which may include the serial)
+/// at trace level.
+fn log_response(rx: &[u8]) {
+	let (data, sw) = rx.split_at(rx.len().saturating_sub(2));
+	log::debug!(
+		"<< SW {} ({} data bytes)",
+		hex::encode_upper(sw),
+		data.len()
+	);
+	log::trace!("<< {}", hex::encode_upper(rx));
+}
+
+/// Transmits an APDU, following `61XX` "more data available" responses with
+/// `GET RESPONSE` until the whole payload has been received.
+///
+/// The returned response is the reassembled data followed by the final status word.
+fn transmit(card: &dyn ApduTransport, apdu: &[u8]) -> Result<Vec<u8>, PFError> {
+	let mut response = Vec::new();
+	log_apdu(apdu);
+	let mut rx = card.transmit(apdu)?;
+
+	loop {
+		log_response(&rx);
+		let n = rx.len();
+		if n < 2 || rx[n - 2] != SW1_MORE_DATA {
+			response.extend_from_slice(&rx);
+			return Ok(response);
+		}
+
+		let remaining = rx[n - 1];
+		log::debug!("Device has more data, fetching {} bytes", remaining);
+		response.extend_from_slice(&rx[..n - 2]);
+
+		// GET RESPONSE APDU: 00 C0 00 00 [Le]
+		let get_response = [APDU_CLA_ISO, APDU_INS_GET_RESPONSE, 0x00, 0x00, remaining];
+		log_apdu(&get_response);
+		rx = card.transmit(&get_response)?;
+	}
+}
+
+/// Transmits an APDU and returns the response data without the status word,
+/// failing if it didn't end with `90 00`
+fn transmit_read(card: &dyn ApduTransport, apdu: &[u8], context: &str) -> Result<Vec<u8>, PFError> {
+	let mut rx = transmit(card, apdu)?;
+	check_status(&rx, context)?;
+	rx.truncate(rx.len() - 2);
+	Ok(rx)
+}
+
+/// Splits `data` into short APDUs using ISO 7816-4 command chaining.
+///
+/// Every chunk except the last has the chaining bit set in CLA. Empty `data` yields a
+/// single APDU without a data field.
+fn chain_apdus(cla: u8, ins: u8, p1: u8, p2: u8, data: &[u8]) -> Vec<Vec<u8>> {
+	if data.is_empty() {
+		return vec![vec![cla, ins, p1, p2]];
+	}
+
+	let chunks: Vec<&[u8]> = data.chunks(APDU_SHORT_MAX_DATA).collect();
+	chunks
+		.iter()
+		.enumerate()
+		.map(|(idx, chunk)| {
+			let last = idx + 1 == chunks.len();
+			let chunk_cla = if last { cla } else { cla | APDU_CLA_CHAINING };
+
+			let mut apdu = vec![chunk_cla, ins, p1, p2, chunk.len() as u8];
+			apdu.extend_from_slice(chunk);
+			apdu
+		})
+		.collect()
+}
+
+/// Progress callback for chained writes, called with `(apdus_sent, apdus_total)`
+pub type Progress<'a> = &'a dyn Fn(usize, usize);
+
+/// `Progress` for callers that don't report it
+fn no_progress(_sent: usize, _total: usize) {}
+
+/// Sends `data` using command chaining, see `chain_apdus`.
+///
+/// Each intermediate chunk must be acknowledged with `90 00`; the response to the final
+/// chunk is returned. `progress` is called after every acknowledged chunk.
+#[allow(clippy::too_many_arguments)]
+fn transmit_chained(
+	card: &dyn ApduTransport,
+	cla: u8,
+	ins: u8,
+	p1: u8,
+	p2: u8,
+	data: &[u8],
+	context: &str,
+	progress: Progress<'_>,
+) -> Result<Vec<u8>, PFError> {
+	let apdus = chain_apdus(cla, ins, p1, p2, data);
+	let mut rx = Vec::new();
+
+	for (idx, apdu) in apdus.iter().enumerate() {
+		log::debug!(
+			"Sending chunk {}/{} ({} bytes)",
+			idx + 1,
+			apdus.len(),
+			apdu.len()
+		);
+		rx = transmit(card, apdu)?;
+		check_status(&rx, context)?;
+		progress(idx + 1, apdus.len());
+	}
+
+	Ok(rx)
+}
+
+/// Lists the names of all smart card readers currently known to PCSC
+pub fn list_readers(state: &PcscState) -> Result<Vec<String>, PFError> {
+	match reader_names(&state.context()?) {
+		Err(e) if is_stale_context(&e) => {
+			state.invalidate();
+			reader_names(&state.context()?)
+		}
+		res => res,
+	}
+}
+
+fn reader_names(ctx: &Context) -> Result<Vec<String>, PFError> {
+	let readers = list_reader_cstrings(ctx)?;
+
+	Ok(readers
+		.iter()
+		.map(|r| r.to_string_lossy().into_owned())
+		.collect())
+}
+
+/// Returns the reader hosting the device with `serial`, or `None` if no reader does.
+///
+/// Every reader is connected to and the Rescue Applet selected, readers that fail (no
+/// card, another applet, in use elsewhere) are skipped. The serial is matched case
+/// insensitively and dashes are ignored, so `DeviceInfo::serial_label` works too.
+pub fn find_device_by_serial(state: &PcscState, serial: &str) -> Result<Option<String>, PFError> {
+	let wanted = serial.trim().replace('-', "");
+	log::info!("Looking for device with serial {}", wanted);
+
+	let readers = match list_readers(state) {
+		Ok(readers) => readers,
+		Err(PFError::Pcsc(pcsc::Error::NoReadersAvailable)) => return Ok(None),
+		Err(e) => return Err(e),
+	};
+
+	for name in readers {
+		match connect_and_select(state, Some(&name)) {
+			Ok((_, select_resp)) => {
+				let found = select_serial(&select_resp);
+				if found
+					.as_deref()
+					.is_some_and(|found| found.eq_ignore_ascii_case(&wanted))
+				{
+					log::info!("Device {} found on reader '{}'", wanted, name);
+					return Ok(Some(name));
+				}
+				log::debug!("Reader '{}' hosts serial {:?}", name, found);
+			}
+			Err(e) => log::debug!("Skipping reader '{}': {}", name, e),
+		}
+	}
+
+	log::info!("No reader hosts device {}", wanted);
+	Ok(None)
+}
+
+/// Lists the raw reader names, growing the buffer when the default one is too small.
+/// Machines with many virtual readers (e.g. PKCS#11 tokens) easily exceed it.
+pub(crate) fn list_reader_cstrings(ctx: &Context) -> Result<Vec<CString>, pcsc::Error> {
+	list_with_growing_buffer(
+		|buf| {
+			ctx.list_readers(buf)
+				.map(|readers| readers.map(CStr::to_owned).collect())
+		},
+		|| ctx.list_readers_len(),
+	)
+}
+
+/// Calls `list` with a `READERS_BUF_LEN` buffer, and again with a larger one for as long
+/// as it fails with `InsufficientBuffer`. `needed_len` reports the size PCSC asks for.
+fn list_with_growing_buffer<T>(
+	mut list: impl FnMut(&mut [u8]) -> Result<T, pcsc::Error>,
+	mut needed_len: impl FnMut() -> Result<usize, pcsc::Error>,
+) -> Result<T, pcsc::Error> {
+	let mut readers_buf = vec![0; READERS_BUF_LEN];
+	loop {
+		match list(&mut readers_buf) {
+			Ok(readers) => return Ok(readers),
+			Err(pcsc::Error::InsufficientBuffer) => {
+				// Readers can appear between the two calls, so the loop checks again
+				let needed = needed_len()?.max(readers_buf.len() * 2);
+				log::debug!(
+					"Reader list needs a larger buffer, growing to {} bytes",
+					needed
+				);
+				readers_buf.resize(needed, 0);
+			}
+			Err(e) => return Err(e),
+		}
+	}
+}
+
+/// Connects to a reader and selects the Rescue Applet.
+///
+/// When `reader_name` is `None` the first available reader is used, otherwise the name
+/// must match one of the listed readers exactly. If the cached context turns out to be
+/// stale it is re-established once and the connection retried.
+fn connect_and_select(
+	state: &PcscState,
+	reader_name: Option<&str>,
+) -> Result<(ConnectedCard, Vec<u8>), PFError> {
+	connect_with_mode(state, reader_name, ShareMode::Shared)
+}
+
+/// Like `connect_and_select`, but no other application can talk to the card until the
+/// returned `Card` is dropped, which disconnects it on every path including errors.
+fn connect_exclusive(
+	state: &PcscState,
+	reader_name: Option<&str>,
+) -> Result<(ConnectedCard, Vec<u8>), PFError> {
+	connect_with_mode(state, reader_name, ShareMode::Exclusive)
+}
+
+fn connect_with_mode(
+	state: &PcscState,
+	reader_name: Option<&str>,
+	mode: ShareMode,
+) -> Result<(ConnectedCard, Vec<u8>), PFError> {
+	with_context_retry(state, |ctx| {
+		let card = try_connect(ctx, reader_name, mode)?;
+		let rx = select_rescue_applet(&card)?;
+		Ok((card, rx))
+	})
+}
 
-/// Connects to the first available reader and selects the Rescue Applet
-fn connect_and_select() -> Result<(pcsc::Card, Vec<u8>), PFError> {
-	let ctx = Context::establish(Scope::User)?;
+/// Connects to a reader without selecting any applet, for commands that must work even
+/// when the Rescue Applet is missing.
+fn connect_unselected(
+	state: &PcscState,
+	reader_name: Option<&str>,
+) -> Result<ConnectedCard, PFError> {
+	with_context_retry(state, |ctx| {
+		try_connect(ctx, reader_name, ShareMode::Shared)
+	})
+}
 
-	let mut readers_buf = [0; 2048];
-	let mut readers = ctx.list_readers(&mut readers_buf)?;
+/// Runs `op` on the cached context, re-establishing it and running `op` once more if it
+/// turned out to be stale
+fn with_context_retry<T>(
+	state: &PcscState,
+	op: impl Fn(&Context) -> Result<T, PFError>,
+) -> Result<T, PFError> {
+	match op(&state.context()?) {
+		Err(e) if is_stale_context(&e) => {
+			log::warn!("PCSC context failed ({}), retrying with a new one", e);
+			state.invalidate();
+			op(&state.context()?)
+		}
+		res => res,
+	}
+}
 
-	// Use the first reader found
-	let reader = readers.next().ok_or_else(|| {
-		log::error!("No Smart Card Reader found");
-		PFError::Device("No Smart Card Reader found.".into())
+fn try_connect(
+	ctx: &Context,
+	reader_name: Option<&str>,
+	mode: ShareMode,
+) -> Result<ConnectedCard, PFError> {
+	let available = list_reader_cstrings(ctx).map_err(|e| match e {
+		pcsc::Error::NoReadersAvailable => PFError::NoReader,
+		e => e.into(),
 	})?;
 
-	let card = ctx.connect(reader, ShareMode::Shared, Protocols::ANY)?;
+	let reader = match reader_name {
+		// Use the first reader found
+		None => available.first().ok_or_else(|| {
+			log::error!("No Smart Card Reader found");
+			PFError::NoReader
+		})?,
+		Some(name) => available
+			.iter()
+			.find(|r| r.to_bytes() == name.as_bytes())
+			.ok_or_else(|| {
+				let names: Vec<String> = available
+					.iter()
+					.map(|r| r.to_string_lossy().into_owned())
+					.collect();
+				log::error!("Reader '{}' not found", name);
+				PFError::Device(format!(
+					"Reader '{}' not found. Available readers: {}",
+					name,
+					if names.is_empty() {
+						"none".to_string()
+					} else {
+						names.join(", ")
+					}
+				))
+			})?,
+	};
+
+	let card = ctx
+		.connect(reader, mode, Protocols::ANY)
+		.map_err(|e| match e {
+			pcsc::Error::SharingViolation => {
+				log::error!("Reader {:?} is in use by another application", reader);
+				PFError::ReaderBusy(reader.to_string_lossy().into_owned())
+			}
+			e => e.into(),
+		})?;
+
+	Ok(ConnectedCard::new(card))
+}
+
+/// Selects the Rescue Applet on an already connected card, returning the select response
+/// including the status word.
+///
+/// If the applet is missing but the device answers on one of the `FALLBACK_AIDS`, a
+/// `PFError::NotInRescueMode` naming that applet is returned.
+pub fn select_rescue_applet(card: &dyn ApduTransport) -> Result<Vec<u8>, PFError> {
+	let rx = select_aid(card, RESCUE_AID)?;
+
+	// Check Success (0x90 0x00)
+	// There is no such mode as fido, i tink the rescue applet stays active and at the same time fido mode works?
+	// Need to study this more.
+	if let Err(e) = check_status(&rx, "Rescue Applet select failed") {
+		let PFError::StatusWord { sw1, sw2 } = e else {
+			return Err(e);
+		};
+		// The applet is installed but switched off, unlike a missing applet this can be fixed
+		if [sw1, sw2] == SW_SELECTED_FILE_DEACTIVATED {
+			log::error!("Rescue Applet is deactivated");
+			return Err(PFError::Device(
+				"Rescue Applet is deactivated, re-enable it first".into(),
+			));
+		}
+		if let Some(applet) = probe_fallback_aids(card)? {
+			return Err(PFError::NotInRescueMode {
+				applet: applet.into(),
+			});
+		}
+		// Nothing answered, most likely a different kind of card. The ATR tells them apart.
+		let atr = card.atr().map(hex::encode_upper);
+		log::error!(
+			"Rescue Applet not found ({}), card ATR: {}",
+			status_word_message(sw1, sw2),
+			atr.as_deref().unwrap_or("unknown")
+		);
+		return Err(PFError::UnknownCard { atr });
+	}
+
+	log::info!("Successfully connected to Rescue Applet");
+	Ok(rx)
+}
 
+/// Sends `SELECT` by DF name for `aid`, returning the raw response including the status word
+fn select_aid(card: &dyn ApduTransport, aid: &[u8]) -> Result<Vec<u8>, PFError> {
 	// Select Applet APDU: 00 A4 04 04 [Len] [AID]
 	let mut apdu = vec![
 		APDU_CLA_ISO,
 		APDU_INS_SELECT,
 		APDU_P1_SELECT_BY_DF_NAME,
 		APDU_P2_RETURN_FCI,
-		RESCUE_AID.len() as u8,
+		aid.len() as u8,
+	];
+	apdu.extend_from_slice(aid);
+
+	transmit(card, &apdu)
+}
+
+/// Tries the `FALLBACK_AIDS` after the Rescue Applet could not be selected, returning the
+/// name of the first applet that answers.
+fn probe_fallback_aids(card: &dyn ApduTransport) -> Result<Option<&'static str>, PFError> {
+	for (name, aid) in FALLBACK_AIDS {
+		let rx = select_aid(card, aid)?;
+		if rx.ends_with(&SW_SUCCESS) {
+			log::warn!("Device answered on the {} applet, not in rescue mode", name);
+			return Ok(Some(name));
+		}
+		log::debug!("{} applet select failed: {}", name, hex::encode_upper(&rx));
+	}
+	Ok(None)
+}
+
+/// Connects exclusively for a multi-step write, so no other application can interleave
+/// transactions between reading the current config and writing the merged one.
+///
+/// Refuses firmware older than `MIN_WRITE_FIRMWARE_VERSION`, whose applet can't parse the
+/// PHY TLV this tool writes. Read-only commands skip the check and use `connect_and_select`.
+fn connect_for_write(
+	state: &PcscState,
+	reader: Option<&str>,
+) -> Result<(ConnectedCard, Vec<u8>), PFError> {
+	let (card, select_resp) = connect_exclusive(state, reader)?;
+
+	let Some(version) = select_version(&select_resp) else {
+		return Err(PFError::Device("Invalid select response".into()));
+	};
+
+	if version < MIN_WRITE_FIRMWARE_VERSION {
+		log::error!(
+			"Firmware {} is older than the minimum {}",
+			version,
+			MIN_WRITE_FIRMWARE_VERSION
+		);
+		return Err(PFError::Device(format!(
+			"Firmware {} too old, need >= {}",
+			version, MIN_WRITE_FIRMWARE_VERSION
+		)));
+	}
+
+	let cached_pin = state.pin.lock().unwrap_or_else(|e| e.into_inner()).clone();
+	if let Some(pin) = cached_pin {
+		// Security state doesn't survive the applet select, so verify again on every write
+		if let Err(e) = send_verify(&card, &pin) {
+			*state.pin.lock().unwrap_or_else(|e| e.into_inner()) = None;
+			return Err(e);
+		}
+	}
+
+	Ok((card, select_resp))
+}
+
+/// Connects, selects the applet and runs `op` on the card, starting over when the card is
+/// reset or removed part way through (common right after a config write, while the
+/// firmware re-applies its settings).
+fn with_card_retry<T>(
+	state: &PcscState,
+	reader: Option<&str>,
+	op: impl Fn(&dyn ApduTransport, &[u8]) -> Result<T, PFError>,
+) -> Result<T, PFError> {
+	let mut attempt = 0;
+	loop {
+		let result =
+			connect_and_select(state, reader).and_then(|(card, select)| op(&card, &select));
+
+		match result {
+			Err(PFError::Pcsc(e @ (pcsc::Error::ResetCard | pcsc::Error::RemovedCard)))
+				if attempt < CARD_RESET_RETRIES =>
+			{
+				attempt += 1;
+				log::warn!(
+					"Card went away ({}), reconnecting (attempt {}/{})",
+					e,
+					attempt,
+					CARD_RESET_RETRIES
+				);
+				std::thread::sleep(CARD_RESET_RETRY_DELAY * attempt);
+			}
+			result => return result,
+		}
+	}
+}
+
+pub fn read_device_details(
+	state: &PcscState,
+	reader: Option<&str>,
+) -> Result<FullDeviceStatus, PFError> {
+	log::info!("Reading full device details");
+	with_card_retry(state, reader, read_device_status)
+}
+
+/// Selects the Rescue Applet and reads the full device status over any transport.
+///
+/// Unlike `read_device_details` this neither manages a PCSC context nor retries, which is
+/// left to the caller.
+pub fn read_device_details_from(card: &dyn ApduTransport) -> Result<FullDeviceStatus, PFError> {
+	let select_resp = select_rescue_applet(card)?;
+	read_device_status(card, &select_resp)
+}
+
+fn read_device_status(
+	card: &dyn ApduTransport,
+	select_resp: &[u8],
+) -> Result<FullDeviceStatus, PFError> {
+	log::trace!("Select Response: {}", hex::encode_upper(select_resp));
+
+	// Select Response: [MCU, Product, Major, Minor, Serial(8)...]
+	let mcu = select_field(select_resp, SELECT_MCU, "MCU")?[0];
+	let product = select_field(select_resp, SELECT_PRODUCT, "product")?[0];
+	let version = select_field(select_resp, SELECT_VERSION, "firmware version")?;
+	let version = FirmwareVersion::new(version[0], version[1]);
+
+	// Older firmware stops after the version, a serial that is cut short is malformed
+	let serial_str = if select_data(select_resp).len() > SELECT_SERIAL.start {
+		hex::encode_upper(select_field(select_resp, SELECT_SERIAL, "serial number")?)
+	} else {
+		log::warn!(
+			"Device did not return a Serial Number (Firmware mismatch?). Using placeholder."
+		);
+		PLACEHOLDER_SERIAL.to_string()
+	};
+
+	log::info!(
+		"Device Hardware: {} / {}",
+		PicoMcu::describe(mcu),
+		PicoProduct::describe(product)
+	);
+	log::info!("Device Version: {}", version);
+	log::debug!("Device Serial: {}", serial_str);
+
+	// The remaining sections are read independently, a failed one is reported in `warnings`
+	// so the rest still shows up
+	let mut warnings = Vec::new();
+
+	// 2. Read Flash Info
+	let flash =
+		partial_read(read_flash_raw(card), "Flash info", &mut warnings)?.unwrap_or_default();
+
+	let mut rdr = Cursor::new(&flash);
+	let _free = rdr.read_u32::<BigEndian>().unwrap_or(0);
+	let used = rdr.read_u32::<BigEndian>().unwrap_or(0);
+	let total = rdr.read_u32::<BigEndian>().unwrap_or(0);
+
+	// Older firmware may stop after Total, missing fields read as 0
+	let nfiles = rdr.read_u32::<BigEndian>().unwrap_or(0);
+	// NOTE: captured but currently unused variable
+	let _chip_size = rdr.read_u32::<BigEndian>().unwrap_or(0);
+	let (flash_free_percent, low_space) = flash_free_stats(used, total);
+
+	let secure_boot = partial_read(
+		read_secure_boot_raw(card),
+		"Secure boot status",
+		&mut warnings,
+	)?
+	.map_or_else(SecureBootState::default, |data| {
+		parse_secure_boot_status(&data)
+	});
+	let phy = partial_read(read_phy_raw(card), "PHY config", &mut warnings)?.unwrap_or_default();
+	let pin_required =
+		partial_read(probe_pin_required(card), "PIN status", &mut warnings)?.unwrap_or(false);
+	let (config, config_warnings) = parse_phy_tlv(&phy);
+	let enabled_curves = RescueCurves::from_bits_truncate(config.curves_raw).names();
+
+	if warnings.is_empty() {
+		log::info!("Successfully read device details - Firmware: {}", version);
+	} else {
+		log::warn!(
+			"Read device details with {} failed section(s) - Firmware: {}",
+			warnings.len(),
+			version
+		);
+	}
+
+	Ok(FullDeviceStatus {
+		info: DeviceInfo {
+			serial_label: format_serial(&serial_str),
+			serial: serial_str,
+			flash_used: used / 1024,
+			flash_total: total / 1024,
+			flash_free_percent,
+			low_space,
+			file_count: nfiles,
+			firmware_version: version.to_string(),
+			firmware: Some(version),
+			mcu,
+			mcu_name: PicoMcu::describe(mcu),
+			product,
+			product_label: PicoProduct::describe(product),
+			boot_count: None,
+			manufacture_date: None,
+		},
+		config,
+		config_warnings,
+		secure_boot: secure_boot.enabled,
+		secure_lock: secure_boot.locked,
+		secure_boot_algorithm: SecureBootAlgorithm::for_mcu(mcu),
+		bootloader_version: secure_boot.bootloader_version,
+		secure_lock_attempts: secure_boot.lock_attempts,
+		enabled_curves,
+		// The PHY config only holds the enabled mask, there is no documented support mask
+		supported_curves: None,
+		pin_required,
+		warnings,
+	})
+}
+
+/// Asks the applet whether a PIN has to be verified before writes, using `VERIFY` without
+/// data. Firmware without a rescue PIN doesn't know the instruction and reports `false`.
+fn probe_pin_required(card: &dyn ApduTransport) -> Result<bool, PFError> {
+	let rx = transmit(
+		card,
+		&[APDU_CLA_ISO, APDU_INS_VERIFY, 0x00, APDU_P2_RESCUE_PIN],
+	)?;
+	Ok(match rx.as_slice() {
+		[.., SW1_PIN_TRIES, _] => true,
+		rx => rx.ends_with(&SW_SECURITY_NOT_SATISFIED),
+	})
+}
+
+/// Sends `VERIFY` with `pin`, mapping a rejected PIN to a message with the tries left
+fn send_verify(card: &dyn ApduTransport, pin: &str) -> Result<(), PFError> {
+	// APDU: 00 20 00 80 [Lc] [PIN]
+	let mut apdu = vec![
+		APDU_CLA_ISO,
+		APDU_INS_VERIFY,
+		0x00,
+		APDU_P2_RESCUE_PIN,
+		pin.len() as u8,
 	];
-	apdu.extend_from_slice(RESCUE_AID);
+	apdu.extend_from_slice(pin.as_bytes());
+
+	let rx = transmit(card, &apdu)?;
+	if let [.., SW1_PIN_TRIES, sw2] = rx.as_slice() {
+		log::error!("PIN rejected, {} tries left", sw2 & 0x0F);
+		return Err(PFError::Device(format!(
+			"Wrong PIN, {} tries left",
+			sw2 & 0x0F
+		)));
+	}
+	check_status(&rx, "PIN verification failed")
+}
+
+/// Returns `Ok(None)` and records a warning when a section read fails with a device error.
+/// PCSC errors are passed on, once the card is gone no other section will read either.
+fn partial_read<T>(
+	result: Result<T, PFError>,
+	section: &str,
+	warnings: &mut Vec<String>,
+) -> Result<Option<T>, PFError> {
+	match result {
+		Ok(val) => Ok(Some(val)),
+		Err(e @ PFError::Pcsc(_)) => Err(e),
+		Err(e) => {
+			log::warn!("{} read failed: {}", section, e);
+			warnings.push(format!("{} read failed: {}", section, e));
+			Ok(None)
+		}
+	}
+}
+
+/// Data of a select response, without the trailing status word
+fn select_data(select_resp: &[u8]) -> &[u8] {
+	&select_resp[..select_resp.len().saturating_sub(2)]
+}
+
+/// One field of a select response, a `PFError::Device` naming it when the response is
+/// too short to hold it
+fn select_field<'a>(
+	select_resp: &'a [u8],
+	range: std::ops::Range<usize>,
+	field: &str,
+) -> Result<&'a [u8], PFError> {
+	let data = select_data(select_resp);
+	data.get(range.clone()).ok_or_else(|| {
+		log::error!(
+			"Select response too short for the {} ({} data bytes): {}",
+			field,
+			data.len(),
+			hex::encode_upper(select_resp)
+		);
+		PFError::Device(format!(
+			"Invalid select response: {} missing (expected bytes {}..{}, got {})",
+			field,
+			range.start,
+			range.end,
+			data.len()
+		))
+	})
+}
+
+/// Firmware version from a select response (`[MCU, Product, Major, Minor, ...]`)
+fn select_version(select_resp: &[u8]) -> Option<FirmwareVersion> {
+	match select_data(select_resp).get(SELECT_VERSION)? {
+		&[major, minor] => Some(FirmwareVersion::new(major, minor)),
+		_ => None,
+	}
+}
+
+/// Serial number from a select response, `None` if the firmware didn't send one
+fn select_serial(select_resp: &[u8]) -> Option<String> {
+	select_data(select_resp)
+		.get(SELECT_SERIAL)
+		.map(hex::encode_upper)
+}
+
+/// Reads the flash info response, without the trailing status word.
+///
+/// Layout: `[Free, Used, Total, Files, Size]`, each a big endian `u32`
+fn read_flash_raw(card: &dyn ApduTransport) -> Result<Vec<u8>, PFError> {
+	transmit_read(
+		card,
+		&[
+			APDU_CLA_PROPRIETARY,
+			RescueInstruction::Read as u8,
+			ReadParam::FlashInfo as u8,
+			P2_UNUSED,
+			0x00, // Le
+		],
+		"Failed to read flash",
+	)
+}
+
+/// Reads the secure boot status response, without the trailing status word.
+///
+/// Layout: `[Enabled, Locked, KeyHash...]`
+fn read_secure_boot_raw(card: &dyn ApduTransport) -> Result<Vec<u8>, PFError> {
+	read_secure_boot_slot_raw(card, 0)
+}
+
+/// Like `read_secure_boot_raw`, with P2 selecting the boot key slot
+fn read_secure_boot_slot_raw(card: &dyn ApduTransport, slot: u8) -> Result<Vec<u8>, PFError> {
+	transmit_read(
+		card,
+		&[
+			APDU_CLA_PROPRIETARY,
+			RescueInstruction::Read as u8,
+			ReadParam::SecureBootStatus as u8,
+			slot,
+			0x00,
+		],
+		"Failed to read secure boot status",
+	)
+}
+
+/// Decoded secure boot read (`[enabled, locked, hash]`), firmware may stop after any field
+/// past the flags.
+#[derive(Debug, Default)]
+struct SecureBootState {
+	enabled: bool,
+	locked: bool,
+	/// Bootloader version.
+	///
+	/// NOTE: The secure boot read ends after the key hash on every known firmware, no
+	/// release defines a version after it, so this is always `None` rather than read from
+	/// a guessed offset.
+	bootloader_version: Option<FirmwareVersion>,
+	/// Lock attempts left.
+	///
+	/// NOTE: No known firmware has a Secure Lock retry counter, the secure boot read
+	/// carries none, so this is always `None` until a firmware documents one.
+	lock_attempts: Option<u8>,
+}
+
+/// Reads the secure boot state, flags default to `false` if unavailable
+fn read_secure_boot_status(card: &dyn ApduTransport) -> Result<SecureBootState, PFError> {
+	let status = match read_secure_boot_raw(card) {
+		Ok(data) => parse_secure_boot_status(&data),
+		Err(e @ PFError::Pcsc(_)) => return Err(e),
+		Err(_) => SecureBootState::default(),
+	};
+
+	Ok(status)
+}
+
+fn parse_secure_boot_status(data: &[u8]) -> SecureBootState {
+	let [enabled, locked, ..] = data else {
+		return SecureBootState::default();
+	};
+
+	SecureBootState {
+		enabled: *enabled != 0,
+		locked: *locked != 0,
+		bootloader_version: None,
+		lock_attempts: None,
+	}
+}
+
+/// Boot key hash from a secure boot read (`[enabled, locked, hash, ...]`)
+fn secure_boot_key_hash(data: &[u8]) -> &[u8] {
+	let end = data.len().min(2 + SECURE_BOOT_KEY_HASH_LEN);
+	data.get(2..end).unwrap_or_default()
+}
+
+/// Reads the raw PHY config TLV, without the trailing status word
+fn read_phy_raw(card: &dyn ApduTransport) -> Result<Vec<u8>, PFError> {
+	read_phy_slot_raw(card, PHY_SLOT_ACTIVE)
+}
+
+/// Like `read_phy_raw`, with P2 selecting the config slot.
+///
+/// A slot the firmware doesn't have is rejected with `6A86` / `6A88`, which is turned into
+/// an error naming the slot so it isn't mistaken for an empty config.
+fn read_phy_slot_raw(card: &dyn ApduTransport, slot: u8) -> Result<Vec<u8>, PFError> {
+	transmit_read(
+		card,
+		&[
+			APDU_CLA_PROPRIETARY,
+			RescueInstruction::Read as u8,
+			ReadParam::PhyConfig as u8,
+			slot,
+			0x00,
+		],
+		"Failed to read config",
+	)
+	.map_err(|e| match e {
+		PFError::StatusWord { sw1, sw2 }
+			if slot != PHY_SLOT_ACTIVE && [SW_WRONG_P1P2, SW_NOT_FOUND].contains(&[sw1, sw2]) =>
+		{
+			log::error!("Firmware has no PHY config slot {}", slot);
+			PFError::Device(format!(
+				"PHY config slot {} is not available on this device",
+				slot
+			))
+		}
+		e => e,
+	})
+}
+
+/// Reads and parses the PHY config TLV, only logging any parse warnings
+fn read_phy_config(card: &dyn ApduTransport) -> Result<AppConfig, PFError> {
+	Ok(parse_phy_tlv(&read_phy_raw(card)?).0)
+}
+
+/// Decodes a BER length at the start of `data` into `(length, bytes used)`.
+///
+/// Handles the short form (`00`-`7F`) and the long forms `81 XX` and `82 XX XX`. Any other
+/// first byte is read as a plain one byte length, the way `push_tlv_length` writes them.
+/// No PHY value reaches 128 bytes, so the two never collide. Returns `None` when the
+/// length bytes are cut off.
+fn tlv_length(data: &[u8]) -> Option<(usize, usize)> {
+	match *data {
+		[0x81, len, ..] => Some((len.into(), 2)),
+		[0x82, hi, lo, ..] => Some((u16::from_be_bytes([hi, lo]).into(), 3)),
+		[0x81 | 0x82, ..] => None,
+		[len, ..] => Some((len.into(), 1)),
+		[] => None,
+	}
+}
+
+/// Appends the length of a `tag` value as the single byte the firmware reads.
+///
+/// Values over `TLV_SHORT_LENGTH_MAX` bytes are rejected instead of being written with a
+/// length form the firmware has never had to read. A longer single byte would start with
+/// the `81`/`82` long form markers `tlv_length` reads.
+fn push_tlv_length(buf: &mut Vec<u8>, tag: u8, len: usize) -> Result<(), PFError> {
+	let len = u8::try_from(len)
+		.ok()
+		.filter(|len| *len <= TLV_SHORT_LENGTH_MAX)
+		.ok_or_else(|| {
+			PFError::Device(format!(
+				"PHY tag {:#04X} value is {} bytes, the maximum is {}",
+				tag, len, TLV_SHORT_LENGTH_MAX
+			))
+		})?;
+	buf.push(len);
+	Ok(())
+}
+
+/// Splits a PHY TLV blob into `(tag, value)` pairs, stopping at the first malformed entry.
+/// Also returns the trailing bytes that couldn't be parsed, empty for a well formed blob.
+fn split_tlv(data: &[u8]) -> (Vec<(u8, &[u8])>, &[u8]) {
+	let mut entries = Vec::new();
+	let mut i = 0;
+	while i < data.len() {
+		let Some((len, len_bytes)) = tlv_length(&data[i + 1..]) else {
+			break;
+		};
+		let start = i + 1 + len_bytes;
+		let Some(val) = data.get(start..start + len) else {
+			break;
+		};
+		entries.push((data[i], val));
+		i = start + len;
+	}
+	(entries, &data[i.min(data.len())..])
+}
+
+fn tlv_entries(data: &[u8]) -> Vec<(u8, &[u8])> {
+	split_tlv(data).0
+}
+
+/// Returns the value of the first occurrence of `tag` in a PHY TLV blob
+fn find_tlv(data: &[u8], tag: PhyTag) -> Option<&[u8]> {
+	tlv_entries(data)
+		.into_iter()
+		.find(|(t, _)| *t == tag as u8)
+		.map(|(_, val)| val)
+}
+
+/// Merges `changes` into the `current` PHY TLV by tag number.
+///
+/// Tags present in both take the new value, tags only in `current` (including ones this
+/// tool doesn't understand) are kept untouched, and new tags are appended. Trailing bytes
+/// of `current` that don't parse as a TLV entry are copied through as they are, after the
+/// new tags so they can't swallow them.
+fn merge_phy_tlv(current: &[u8], changes: &[u8]) -> Result<Vec<u8>, PFError> {
+	let changes = tlv_entries(changes);
+	let mut merged = Vec::new();
+	let mut push = |tag: u8, val: &[u8]| {
+		merged.push(tag);
+		push_tlv_length(&mut merged, tag, val.len())?;
+		merged.extend_from_slice(val);
+		Ok::<_, PFError>(())
+	};
+
+	let (current, tail) = split_tlv(current);
+	for &(tag, val) in &current {
+		match changes.iter().find(|(t, _)| *t == tag) {
+			Some(&(_, new_val)) => push(tag, new_val)?,
+			None => push(tag, val)?,
+		}
+	}
+	for &(tag, val) in &changes {
+		if !current.iter().any(|(t, _)| *t == tag) {
+			push(tag, val)?;
+		}
+	}
+
+	if !tail.is_empty() {
+		log::warn!(
+			"Keeping {} trailing PHY config bytes that aren't valid TLV",
+			tail.len()
+		);
+		merged.extend_from_slice(tail);
+	}
+	Ok(merged)
+}
+
+/// Parses the PHY config TLV (without the trailing status word) into an `AppConfig`.
+///
+/// Known tags with a too short value are skipped with a warning and parsing continues.
+/// A length that overruns the buffer leaves no way to find the next tag, so parsing stops
+/// there, also recording a warning.
+fn parse_phy_tlv(data: &[u8]) -> (AppConfig, Vec<ParseWarning>) {
+	let mut config = AppConfig {
+		// Firmware enables every interface until TAG_ENABLED_USB_ITF says otherwise
+		ccid_enabled: Some(true),
+		..Default::default()
+	};
+	let mut warnings = Vec::new();
+	let mut warn = |tag: u8, message: String| {
+		log::warn!("PHY config: {}", message);
+		warnings.push(ParseWarning { tag, message });
+	};
+
+	let mut i = 0;
+	while i < data.len() {
+		let tag_byte = data[i];
+		let Some((len, len_bytes)) = tlv_length(&data[i + 1..]) else {
+			warn(
+				tag_byte,
+				format!("tag 0x{:02X} has a missing or invalid length", tag_byte),
+			);
+			break;
+		};
+		i += 1 + len_bytes;
+		if i + len > data.len() {
+			warn(
+				tag_byte,
+				format!(
+					"tag 0x{:02X} had bad length {} (only {} bytes left)",
+					tag_byte,
+					len,
+					data.len() - i
+				),
+			);
+			break;
+		}
+		let val = &data[i..i + len];
+		i += len;
+
+		let Some(tag) = PhyTag::from_u8(tag_byte) else {
+			continue;
+		};
+
+		if val.len() < tag.min_len() {
+			warn(
+				tag_byte,
+				format!(
+					"tag 0x{:02X} had bad length {} (expected at least {})",
+					tag_byte,
+					val.len(),
+					tag.min_len()
+				),
+			);
+			continue;
+		}
+
+		match tag {
+			PhyTag::VidPid => {
+				let vid = u16::from_be_bytes([val[0], val[1]]);
+				let pid = u16::from_be_bytes([val[2], val[3]]);
+				config.vid = format!("{:04X}", vid);
+				config.pid = format!("{:04X}", pid);
+			}
+			PhyTag::LedGpio => config.led_gpio = val[0],
+			PhyTag::LedBrightness => config.led_brightness = val[0],
+			PhyTag::PresenceTimeout => config.touch_timeout = val[0],
+			PhyTag::UsbProduct => {
+				let s = std::str::from_utf8(val)
+					.unwrap_or("")
+					.trim_matches(char::from(0));
+				config.product_name = s.to_string();
+			}
+			PhyTag::Opts => {
+				let opts_val = u16::from_be_bytes([val[0], val[1]]);
+				let opts = RescueOptions::from_bits_truncate(opts_val);
+				config.opts_raw = opts_val;
+
+				config.led_dimmable = opts.contains(RescueOptions::LED_DIMMABLE);
+				config.power_cycle_on_reset = opts.power_reset();
+				config.led_steady = opts.contains(RescueOptions::LED_STEADY);
+			}
+			PhyTag::Curves => {
+				let curves_val = u32::from_be_bytes([val[0], val[1], val[2], val[3]]);
+				let curves = RescueCurves::from_bits_truncate(curves_val);
+				config.curves_raw = curves_val;
+				config.enable_secp256k1 = curves.contains(RescueCurves::SECP256K1);
+				config.enable_secp256r1 = curves.contains(RescueCurves::SECP256R1);
+				config.enable_ed25519 = curves.contains(RescueCurves::ED25519);
+				config.enable_ed448 = curves.contains(RescueCurves::ED448);
+			}
+			PhyTag::LedDriver => {
+				let driver = LedDriver::from(val[0]);
+				if !LedDriver::KNOWN.contains(&driver) {
+					warn(tag_byte, format!("unknown LED driver id 0x{:02X}", val[0]));
+				}
+				config.led_driver = Some(driver);
+			}
+			// The other interfaces are reported by `read_usb_mode`
+			PhyTag::UsbInterfaces => {
+				let itf = UsbInterfaces::from_bits_retain(val[0]);
+				config.ccid_enabled = Some(itf.contains(UsbInterfaces::CCID));
+			}
+		}
+	}
+
+	(config, warnings)
+}
+
+/// Rejects values the hardware can't use before anything is sent to the device
+fn validate_config(config: &AppConfigInput) -> Result<(), PFError> {
+	if let Some(gpio) = config.led_gpio.filter(|g| !LED_GPIO_VALID.contains(g)) {
+		return Err(PFError::ValueOutOfRange {
+			field: "ledGpio".into(),
+			value: gpio.into(),
+			min: (*LED_GPIO_VALID.start()).into(),
+			max: (*LED_GPIO_VALID.end()).into(),
+		});
+	}
+
+	if let Some(brightness) = config.led_brightness.filter(|b| *b > LED_BRIGHTNESS_MAX) {
+		return Err(PFError::ValueOutOfRange {
+			field: "ledBrightness".into(),
+			value: brightness.into(),
+			min: 0,
+			max: LED_BRIGHTNESS_MAX.into(),
+		});
+	}
+
+	// An id the firmware has no driver for can leave the LED uninitialised
+	if let Some(driver) = config.led_driver.filter(|d| !LedDriver::KNOWN.contains(d)) {
+		return Err(PFError::NotAccepted {
+			field: "ledDriver".into(),
+			value: u8::from(driver).into(),
+			accepted: LedDriver::KNOWN
+				.iter()
+				.map(|&d| u8::from(d).into())
+				.collect(),
+		});
+	}
+
+	Ok(())
+}
+
+/// Maps a board pin label (`GP25`, `GPIO25`, `LED`...) to its GPIO number
+fn resolve_led_pin_label(label: &str) -> Result<u8, PFError> {
+	let label = label.trim().to_ascii_uppercase();
+
+	let named = LED_PIN_LABELS
+		.iter()
+		.find(|(name, _)| *name == label)
+		.map(|(_, gpio)| *gpio);
+	let numbered = label
+		.strip_prefix("GPIO")
+		.or_else(|| label.strip_prefix("GP"))
+		.and_then(|n| n.parse::<u8>().ok())
+		.filter(|gpio| LED_GPIO_VALID.contains(gpio));
+
+	named.or(numbered).ok_or_else(|| {
+		let names: Vec<&str> = LED_PIN_LABELS.iter().map(|(name, _)| *name).collect();
+		PFError::Io(format!(
+			"Unknown LED pin label '{}'. Accepted: GP{}-GP{}, GPIO{}-GPIO{}, {}",
+			label,
+			LED_GPIO_VALID.start(),
+			LED_GPIO_VALID.end(),
+			LED_GPIO_VALID.start(),
+			LED_GPIO_VALID.end(),
+			names.join(", ")
+		))
+	})
+}
+
+/// Maps a user facing presence timeout in seconds to the `TAG_PRESENCE_TIMEOUT` byte.
+///
+/// The firmware stores whole seconds, but 0 disables the presence check entirely, so it
+/// is only accepted when explicitly allowed.
+fn touch_timeout_to_raw(seconds: u8, allow_disable: bool) -> Result<u8, PFError> {
+	if seconds == TOUCH_TIMEOUT_DISABLED && !allow_disable {
+		log::error!("Refusing to disable presence checking without allow_touch_disable");
+		return Err(PFError::Io(
+			"A touch timeout of 0 disables presence checking, set allow_touch_disable to confirm"
+				.into(),
+		));
+	}
+
+	Ok(seconds)
+}
+
+/// Parses a USB VID/PID given as exactly 4 hex digits, with an optional `0x` prefix.
+/// `field` is the input field name reported in errors.
+fn parse_usb_id(input: &str, field: &str) -> Result<u16, PFError> {
+	let trimmed = input.trim();
+	let digits = trimmed
+		.strip_prefix("0x")
+		.or_else(|| trimmed.strip_prefix("0X"))
+		.unwrap_or(trimmed);
+
+	if digits.len() != 4 {
+		return Err(PFError::InvalidHex {
+			field: field.into(),
+			reason: format!("must be 4 hex digits, got '{}'", input),
+		});
+	}
+	if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+		return Err(PFError::InvalidHex {
+			field: field.into(),
+			reason: format!("must only contain hex digits (0-9, A-F), got '{}'", input),
+		});
+	}
+
+	// Can't fail, 4 hex digits always fit a u16
+	Ok(u16::from_str_radix(digits, 16).unwrap_or_default())
+}
+
+/// Appends a single PHY TLV entry to `buf`
+fn push_tlv(buf: &mut Vec<u8>, tag: PhyTag, value: &[u8]) -> Result<(), PFError> {
+	buf.push(tag as u8);
+	push_tlv_length(buf, tag as u8, value.len())?;
+	buf.extend_from_slice(value);
+	Ok(())
+}
+
+fn push_tlv_u8(buf: &mut Vec<u8>, tag: PhyTag, value: u8) -> Result<(), PFError> {
+	push_tlv(buf, tag, &[value])
+}
+
+/// Multi-byte PHY values are big endian
+fn push_tlv_u16(buf: &mut Vec<u8>, tag: PhyTag, value: u16) -> Result<(), PFError> {
+	push_tlv(buf, tag, &value.to_be_bytes())
+}
+
+fn push_tlv_u32(buf: &mut Vec<u8>, tag: PhyTag, value: u32) -> Result<(), PFError> {
+	push_tlv(buf, tag, &value.to_be_bytes())
+}
+
+/// Builds the PHY TLV holding the changes in `config`.
+///
+/// `current` is the device's PHY config, the opts and curves masks start from its values
+/// so bits this tool doesn't render are preserved. Returns an empty TLV if nothing changes.
+fn build_config_tlv(config: &AppConfigInput, current: &[u8]) -> Result<Vec<u8>, PFError> {
+	validate_config(config)?;
+
+	let mut tlv = Vec::new();
+
+	// VID:PID (Tag 0x00)
+	if let (Some(vid_str), Some(pid_str)) = (&config.vid, &config.pid) {
+		let vid = parse_usb_id(vid_str, "vid")?;
+		let pid = parse_usb_id(pid_str, "pid")?;
+
+		let mut val = vid.to_be_bytes().to_vec();
+		val.extend_from_slice(&pid.to_be_bytes());
+		push_tlv(&mut tlv, PhyTag::VidPid, &val)?;
+	}
+
+	// LED GPIO (Tag 0x04)
+	// An explicit GPIO number wins over a pin label
+	let led_gpio = match (config.led_gpio, &config.led_pin_label) {
+		(None, Some(label)) => Some(resolve_led_pin_label(label)?),
+		(gpio, _) => gpio,
+	};
+	if let Some(val) = led_gpio {
+		push_tlv_u8(&mut tlv, PhyTag::LedGpio, val)?;
+	}
+
+	// LED Brightness (Tag 0x05)
+	if let Some(val) = config.led_brightness {
+		push_tlv_u8(&mut tlv, PhyTag::LedBrightness, val)?;
+	}
+
+	// Touch Timeout (Tag 0x08)
+	if let Some(seconds) = config.touch_timeout {
+		let raw = touch_timeout_to_raw(seconds, config.allow_touch_disable.unwrap_or(false))?;
+		push_tlv_u8(&mut tlv, PhyTag::PresenceTimeout, raw)?;
+	}
+
+	// Options (Tag 0x06)
+	// Each flag is applied on its own on top of the device's current value, so a single
+	// flag can change and bits this tool doesn't render are preserved.
+	// NOTE: `DISABLE_POWER_RESET` is inverted relative to `power_cycle_on_reset`, so it goes
+	// through `RescueOptions::set_power_reset` instead of the plain flag list.
+	let opt_changes = [
+		(config.led_dimmable, RescueOptions::LED_DIMMABLE),
+		(config.led_steady, RescueOptions::LED_STEADY),
+	];
+
+	if config.power_cycle_on_reset.is_some() || opt_changes.iter().any(|(set, _)| set.is_some()) {
+		let mut opts = find_tlv(current, PhyTag::Opts)
+			.filter(|val| val.len() >= 2)
+			.map(|val| RescueOptions::from_bits_retain(u16::from_be_bytes([val[0], val[1]])))
+			.unwrap_or(RescueOptions::empty());
+
+		for (set, flag) in opt_changes {
+			if let Some(set) = set {
+				opts.set(flag, set);
+			}
+		}
+		if let Some(enabled) = config.power_cycle_on_reset {
+			opts.set_power_reset(enabled);
+		}
+
+		push_tlv_u16(&mut tlv, PhyTag::Opts, opts.bits())?;
+	}
+
+	// LED Driver (Tag 0x0C)
+	if let Some(driver) = config.led_driver {
+		push_tlv_u8(&mut tlv, PhyTag::LedDriver, driver.into())?;
+	}
+
+	// Product Name (Tag 0x09)
+	// NOTE: The firmware stores the product string in a fixed 32 byte buffer, so this limit
+	// is imposed by the applet itself and not by the APDU length.
+	if let Some(name) = config.product_name.as_ref().filter(|name| !name.is_empty()) {
+		let mut val = name.as_bytes().to_vec();
+		val.push(0x00); // Null terminator
+		if val.len() > USB_PRODUCT_MAX_LEN {
+			return Err(PFError::TooLong {
+				field: "productName".into(),
+				max: USB_PRODUCT_MAX_LEN - 1,
+			});
+		}
+
+		push_tlv(&mut tlv, PhyTag::UsbProduct, &val)?;
+	}
+
+	let curve_changes = [
+		(config.enable_secp256k1, RescueCurves::SECP256K1),
+		(config.enable_secp256r1, RescueCurves::SECP256R1),
+		(config.enable_ed25519, RescueCurves::ED25519),
+		(config.enable_ed448, RescueCurves::ED448),
+	];
+	let curves_changed = curve_changes.iter().any(|(enabled, _)| enabled.is_some());
+
+	// Curves (Tag 0x0A)
+	// Start from the device's current mask so curves this tool doesn't know about stay enabled.
+	if curves_changed {
+		let mut curves = find_tlv(current, PhyTag::Curves)
+			.filter(|val| val.len() >= 4)
+			.map(|val| {
+				RescueCurves::from_bits_retain(u32::from_be_bytes([val[0], val[1], val[2], val[3]]))
+			})
+			.unwrap_or(RescueCurves::empty());
+
+		for (enabled, curve) in curve_changes {
+			if let Some(enabled) = enabled {
+				curves.set(curve, enabled);
+			}
+		}
+
+		push_tlv_u32(&mut tlv, PhyTag::Curves, curves.bits())?;
+	}
+
+	Ok(tlv)
+}
+
+/// Rejects an LED GPIO in `tlv` that the MCU in the select response doesn't have.
+/// `validate_config` only knows the widest range, since it runs before connecting.
+fn check_led_gpio(tlv: &[u8], select_resp: &[u8]) -> Result<(), PFError> {
+	let mcu = select_data(select_resp).first().copied().unwrap_or(0);
+	let valid = PicoMcu::led_gpio_range(mcu);
+	match find_tlv(tlv, PhyTag::LedGpio) {
+		Some(&[gpio]) if !valid.contains(&gpio) => Err(PFError::ValueOutOfRange {
+			field: "ledGpio".into(),
+			value: gpio.into(),
+			min: (*valid.start()).into(),
+			max: (*valid.end()).into(),
+		}),
+		_ => Ok(()),
+	}
+}
+
+/// Fails unless the select response carries `expected` as its serial
+fn check_expected_serial(select_resp: &[u8], expected: &str) -> Result<(), PFError> {
+	let actual = select_serial(select_resp);
+	if actual
+		.as_deref()
+		.is_some_and(|serial| serial.eq_ignore_ascii_case(expected.trim()))
+	{
+		return Ok(());
+	}
+
+	log::error!("Serial mismatch, refusing to write config");
+	Err(PFError::Device(format!(
+		"Connected device serial {} does not match expected {}",
+		actual.as_deref().unwrap_or("unknown"),
+		expected
+	)))
+}
+
+/// Writes the given config changes to the device.
+///
+/// With `expected_serial` set, nothing is written unless the connected device has that
+/// serial, guarding against the key being swapped between reading and writing.
+/// With `verify` set the PHY config is read back afterwards and every changed tag compared
+/// with what was sent. With `blink_on_error` set a failed write flashes the LED in an error
+/// pattern (see `blink_error_pattern`) before the error is returned.
+/// Tags already holding the requested value are left out to spare the flash, unless
+/// `force` is set.
+#[allow(clippy::too_many_arguments)]
+pub fn write_config(
+	state: &PcscState,
+	config: AppConfigInput,
+	expected_serial: Option<&str>,
+	verify: bool,
+	blink_on_error: bool,
+	force: bool,
+	reader: Option<&str>,
+	progress: Progress<'_>,
+) -> Result<WriteResult, PFError> {
+	let result = apply_config(
+		state,
+		config,
+		expected_serial,
+		verify,
+		force,
+		reader,
+		progress,
+	);
+
+	if let (true, Err(e)) = (blink_on_error, &result) {
+		log::warn!("Signalling failed write ({}) on the device LED", e);
+		blink_error_pattern(state, reader);
+	}
+
+	result
+}
+
+fn apply_config(
+	state: &PcscState,
+	config: AppConfigInput,
+	expected_serial: Option<&str>,
+	verify: bool,
+	force: bool,
+	reader: Option<&str>,
+	progress: Progress<'_>,
+) -> Result<WriteResult, PFError> {
+	log::info!("Writing configuration to device");
+	log::debug!("Config input: {:?}", config);
+
+	// 1. Construct TLV Blob
+	// A dry build without device values validates the input and catches no-op writes
+	// before connecting.
+	if build_config_tlv(&config, &[])?.is_empty() {
+		log::warn!("No configuration changes to apply");
+		return Ok(WriteResult::no_changes());
+	}
+
+	// 2. Connect and Send
+	let (mut card, select_resp) = connect_for_write(state, reader)?;
+
+	if let Some(expected) = expected_serial {
+		check_expected_serial(&select_resp, expected)?;
+	}
+
+	let current = read_phy_raw(&card)?;
+	let mut tlv = build_config_tlv(&config, &current)?;
+	check_led_gpio(&tlv, &select_resp)?;
+	if !force {
+		tlv = drop_unchanged_tags(&tlv, &current)?;
+		if tlv.is_empty() {
+			log::info!("Device already holds the requested configuration");
+			return Ok(WriteResult::no_changes());
+		}
+	}
+
+	log::debug!("TLV payload size: {} bytes", tlv.len());
+
+	// The firmware re-applies its config on reset, so leave it freshly reset for whatever
+	// reads the device next, whether or not the write went through
+	card.set_disposition(Disposition::ResetCard);
+
+	write_phy_merged(&card, &current, &tlv, progress)?;
+
+	if verify {
+		verify_on_card(&mut card, &tlv)?;
+		log::info!("Configuration write verified");
+	}
+
+	log::info!("Configuration applied successfully");
+	Ok(WriteResult {
+		applied: true,
+		tags_written: tlv_entries(&tlv).into_iter().map(|(tag, _)| tag).collect(),
+		verified: verify,
+		message: "Configuration Applied Successfully".into(),
+	})
+}
+
+/// Removes the entries of `changes` whose tag already holds the same value in `current`
+fn drop_unchanged_tags(changes: &[u8], current: &[u8]) -> Result<Vec<u8>, PFError> {
+	let current = tlv_entries(current);
+	let mut changed = Vec::new();
+	for (tag, val) in tlv_entries(changes) {
+		if current.iter().any(|&(t, v)| t == tag && v == val) {
+			log::debug!("Tag 0x{:02X} unchanged, skipping", tag);
+			continue;
+		}
+		changed.push(tag);
+		push_tlv_length(&mut changed, tag, val.len())?;
+		changed.extend_from_slice(val);
+	}
+	Ok(changed)
+}
+
+/// Builds the payload `write_config` would send for `config` without writing anything.
+///
+/// The device is still read, since the merged payload depends on its current config.
+pub fn preview_write_config(
+	state: &PcscState,
+	config: AppConfigInput,
+	reader: Option<&str>,
+) -> Result<WritePreview, PFError> {
+	log::info!("Previewing configuration write");
+	let (card, select_resp) = connect_and_select(state, reader)?;
+
+	let current = read_phy_raw(&card)?;
+	let tlv = drop_unchanged_tags(&build_config_tlv(&config, &current)?, &current)?;
+	check_led_gpio(&tlv, &select_resp)?;
+	let merged = merge_phy_tlv(&current, &tlv)?;
+
+	// write_config doesn't send anything without changes
+	let apdus = if tlv.is_empty() {
+		Vec::new()
+	} else {
+		chain_apdus(
+			APDU_CLA_PROPRIETARY,
+			RescueInstruction::Write as u8,
+			WriteParam::PhyConfig as u8,
+			P2_UNUSED,
+			&merged,
+		)
+	};
+
+	Ok(WritePreview {
+		changes_hex: hex::encode_upper(&tlv),
+		payload_hex: hex::encode_upper(&merged),
+		apdus: apdus.iter().map(hex::encode_upper).collect(),
+	})
+}
+
+/// Config changes collected by `stage_config` until `commit_staged` writes them.
+/// Registered as Tauri managed state, only kept for as long as the app runs.
+#[derive(Default)]
+pub struct StagedConfig(Mutex<Option<AppConfigInput>>);
+
+/// Adds `changes` to the staged config, later values win. Nothing is sent to the device,
+/// but the combined input is validated so a bad value is reported when it is staged.
+pub fn stage_config(
+	staged: &StagedConfig,
+	changes: AppConfigInput,
+) -> Result<AppConfigInput, PFError> {
+	let mut staged = staged.0.lock().unwrap_or_else(|e| e.into_inner());
+	let combined = staged.clone().unwrap_or_default().overlay(changes);
+	build_config_tlv(&combined, &[])?;
+
+	log::debug!("Staged config: {:?}", combined);
+	*staged = Some(combined.clone());
+	Ok(combined)
+}
+
+/// Drops the staged config without writing it, returns whether anything was staged
+pub fn discard_staged(staged: &StagedConfig) -> bool {
+	let discarded = staged
+		.0
+		.lock()
+		.unwrap_or_else(|e| e.into_inner())
+		.take()
+		.is_some();
+	log::info!("Discarded staged config: {}", discarded);
+	discarded
+}
+
+/// Writes the staged config as one merged PHY write and verifies it.
+///
+/// If the write or its verification fails the PHY config read before the write is written
+/// back, so the device ends up either fully configured or as it was. The staged changes
+/// are only cleared once the write is verified, a failed commit can be retried.
+pub fn commit_staged(
+	state: &PcscState,
+	staged: &StagedConfig,
+	reader: Option<&str>,
+) -> Result<WriteResult, PFError> {
+	let mut staged = staged.0.lock().unwrap_or_else(|e| e.into_inner());
+	let Some(config) = staged.as_ref() else {
+		log::warn!("No staged configuration to commit");
+		return Ok(WriteResult::no_changes());
+	};
+	log::info!("Committing staged configuration");
+
+	let (mut card, select_resp) = connect_for_write(state, reader)?;
+	let snapshot = read_phy_raw(&card)?;
+	let tlv = drop_unchanged_tags(&build_config_tlv(config, &snapshot)?, &snapshot)?;
+	check_led_gpio(&tlv, &select_resp)?;
+	if tlv.is_empty() {
+		*staged = None;
+		return Ok(WriteResult::no_changes());
+	}
+	card.set_disposition(Disposition::ResetCard);
+
+	let result = write_phy_merged(&card, &snapshot, &tlv, &no_progress)
+		.and_then(|_| verify_on_card(&mut card, &tlv));
+	// Release the exclusive connection, a restore below opens its own
+	drop(card);
+
+	if let Err(e) = result {
+		log::error!("Staged commit failed ({}), restoring previous config", e);
+		// A fresh write connection, so a cached PIN is verified again
+		let restored =
+			connect_for_write(state, reader).and_then(|(card, _)| write_phy_raw(&card, &snapshot));
+		match restored {
+			Ok(()) => log::info!("Previous config restored"),
+			Err(restore) => {
+				log::error!("Failed to restore previous config: {}", restore);
+				return Err(PFError::Device(format!(
+					"{}, and restoring the previous config failed: {}",
+					e, restore
+				)));
+			}
+		}
+		return Err(e);
+	}
+
+	*staged = None;
+	log::info!("Staged configuration committed and verified");
+	Ok(WriteResult {
+		applied: true,
+		tags_written: tlv_entries(&tlv).into_iter().map(|(tag, _)| tag).collect(),
+		verified: true,
+		message: "Configuration Applied Successfully".into(),
+	})
+}
+
+/// Verifies a write on the exclusive connection that made it, so no other application can
+/// change the config between the write and the read back.
+///
+/// The firmware may reset the card while applying the new config, the same handle is then
+/// reconnected and the applet selected again before reading.
+fn verify_on_card(card: &mut ConnectedCard, changes: &[u8]) -> Result<(), PFError> {
+	let mut attempt = 0;
+	loop {
+		match verify_phy_write(&*card, changes) {
+			Err(PFError::Pcsc(pcsc::Error::ResetCard)) if attempt < CARD_RESET_RETRIES => {
+				attempt += 1;
+				log::warn!(
+					"Card was reset after the write, reconnecting to verify (attempt {}/{})",
+					attempt,
+					CARD_RESET_RETRIES
+				);
+				std::thread::sleep(CARD_RESET_RETRY_DELAY * attempt);
+				card.reconnect(ShareMode::Exclusive)?;
+				select_rescue_applet(&*card)?;
+			}
+			result => return result,
+		}
+	}
+}
+
+/// Re-reads the PHY config and checks that every tag in `changes` holds the value sent
+fn verify_phy_write(card: &dyn ApduTransport, changes: &[u8]) -> Result<(), PFError> {
+	let stored = read_phy_raw(card)?;
+	let stored = tlv_entries(&stored);
+
+	for (tag, sent) in tlv_entries(changes) {
+		let actual = stored.iter().find(|(t, _)| *t == tag).map(|&(_, val)| val);
+		if actual != Some(sent) {
+			log::error!(
+				"Verification failed for tag 0x{:02X}: sent {}, stored {}",
+				tag,
+				hex::encode_upper(sent),
+				actual.map_or_else(|| "nothing".into(), hex::encode_upper)
+			);
+			return Err(PFError::Device(format!(
+				"verification failed for tag 0x{:02X}",
+				tag
+			)));
+		}
+	}
+
+	Ok(())
+}
+
+/// Reads the device's current PHY config and writes `changes` merged into it
+fn write_phy_tlv(card: &dyn ApduTransport, changes: &[u8]) -> Result<(), PFError> {
+	let current = read_phy_raw(card)?;
+	write_phy_merged(card, &current, changes, &no_progress)
+}
+
+/// Writes `changes` merged into an already read `current` PHY TLV.
+///
+/// The applet replaces the whole PHY config on write, so sending only the changed tags
+/// would drop everything else.
+fn write_phy_merged(
+	card: &dyn ApduTransport,
+	current: &[u8],
+	changes: &[u8],
+	progress: Progress<'_>,
+) -> Result<(), PFError> {
+	let merged = merge_phy_tlv(current, changes)?;
+	log::debug!(
+		"Merged TLV payload size: {} bytes ({} bytes changed)",
+		merged.len(),
+		changes.len()
+	);
+
+	write_phy_raw_progress(card, &merged, progress)
+}
+
+/// Writes `data` as the complete PHY config, replacing whatever the device holds
+fn write_phy_raw(card: &dyn ApduTransport, data: &[u8]) -> Result<(), PFError> {
+	write_phy_raw_progress(card, data, &no_progress)
+}
+
+fn write_phy_raw_progress(
+	card: &dyn ApduTransport,
+	data: &[u8],
+	progress: Progress<'_>,
+) -> Result<(), PFError> {
+	// APDU: 80 1C 01 00 [Lc] [Data]
+	transmit_chained(
+		card,
+		APDU_CLA_PROPRIETARY,
+		RescueInstruction::Write as u8,
+		WriteParam::PhyConfig as u8,
+		P2_UNUSED,
+		data,
+		"Write failed",
+		progress,
+	)
+	.map_err(write_error)?;
+
+	Ok(())
+}
+
+/// Turns the status words a write can be refused with into the errors the UI acts on.
+///
+/// NOTE: Firmware that wants secure messaging answers `6987` / `6988`. This tool doesn't
+/// negotiate a secure channel, plain firmware never asks for one, so those writes fail
+/// with `SecureChannelRequired` instead of a bare status word.
+fn write_error(e: PFError) -> PFError {
+	match e {
+		PFError::StatusWord { sw1, sw2 } => match [sw1, sw2] {
+			SW_SECURITY_NOT_SATISFIED => PFError::PinRequired,
+			SW_SM_OBJECTS_MISSING | SW_SM_OBJECTS_INCORRECT => {
+				log::error!("Firmware requires a secure channel for writes");
+				PFError::SecureChannelRequired
+			}
+			_ => e,
+		},
+		e => e,
+	}
+}
+
+fn write_led_brightness(card: &dyn ApduTransport, brightness: u8) -> Result<(), PFError> {
+	let mut tlv = Vec::new();
+	push_tlv_u8(&mut tlv, PhyTag::LedBrightness, brightness)?;
+	write_phy_tlv(card, &tlv)
+}
+
+/// Restores the original LED brightness when dropped, so an identify sequence that fails
+/// partway through never leaves the LED stuck at a different level.
+struct BrightnessGuard<'a> {
+	card: &'a dyn ApduTransport,
+	original: u8,
+	restored: bool,
+}
+
+impl BrightnessGuard<'_> {
+	fn restore(mut self) -> Result<(), PFError> {
+		self.restored = true;
+		write_led_brightness(self.card, self.original)
+	}
+}
+
+impl Drop for BrightnessGuard<'_> {
+	fn drop(&mut self) {
+		if self.restored {
+			return;
+		}
+		if let Err(e) = write_led_brightness(self.card, self.original) {
+			log::error!("Failed to restore LED brightness {}: {}", self.original, e);
+		}
+	}
+}
+
+/// Flashes the LED in a fast burst distinct from `identify_device`, for rigs without a
+/// screen. Every toggle is a PHY config write, so the burst is kept to `ERROR_BLINK_COUNT`
+/// cycles. Best effort: the device may be why the write failed, so problems are only
+/// logged and the brightness is restored where possible (see `blink_led`).
+///
+/// Connects like any write, so firmware too old to write or a failed PIN check skips the
+/// blink as well.
+fn blink_error_pattern(state: &PcscState, reader: Option<&str>) {
+	let blink = connect_for_write(state, reader)
+		.and_then(|(card, _)| blink_led(&card, ERROR_BLINK_COUNT, ERROR_BLINK_INTERVAL));
+
+	if let Err(e) = blink {
+		log::warn!("Could not blink the error pattern: {}", e);
+	}
+}
+
+/// Toggles the LED between max and off `count` times, `interval` apart, and restores the
+/// brightness read beforehand. Returns that brightness.
+///
+/// The firmware ignores the brightness unless `LED_DIMMABLE` is set, on such devices this
+/// fails without writing anything since the blinks would not be visible.
+fn blink_led(card: &dyn ApduTransport, count: usize, interval: Duration) -> Result<u8, PFError> {
+	let config = read_phy_config(card)?;
+	if !config.led_dimmable {
+		log::error!("Can't blink the LED: LED_DIMMABLE is not set");
+		return Err(PFError::Device(
+			"LED dimming is off, the brightness can't be changed to blink the LED".into(),
+		));
+	}
+
+	let guard = BrightnessGuard {
+		card,
+		original: config.led_brightness,
+		restored: false,
+	};
+
+	for _ in 0..count {
+		write_led_brightness(card, LED_BRIGHTNESS_MAX)?;
+		std::thread::sleep(interval);
+		write_led_brightness(card, 0)?;
+		std::thread::sleep(interval);
+	}
+
+	guard.restore()?;
+	Ok(config.led_brightness)
+}
+
+/// Blinks the device LED so the user can tell which key is being configured.
+///
+/// The applet has no dedicated identify instruction, so this toggles `TAG_LED_BRIGHTNESS`
+/// between max and off and then restores the brightness read beforehand, see `blink_led`.
+/// Every toggle is a PHY config write, so the blinks are kept few.
+pub fn identify_device(state: &PcscState, reader: Option<&str>) -> Result<String, PFError> {
+	log::info!("Identifying device by blinking its LED");
+	let (card, _) = connect_for_write(state, reader)?;
+
+	let original = blink_led(&card, IDENTIFY_BLINK_COUNT, IDENTIFY_BLINK_INTERVAL)?;
+
+	log::info!(
+		"Identify sequence finished, brightness restored to {}",
+		original
+	);
+	Ok("Identify sequence complete".into())
+}
+
+/// LED settings from before `set_led_enabled` turned the LED off
+#[derive(Debug, Clone, Copy)]
+struct SavedLed {
+	brightness: u8,
+	dimmable: bool,
+	steady: bool,
+}
+
+/// LED settings remembered by `set_led_enabled`, keyed by device serial.
+/// Registered as Tauri managed state, only kept for as long as the app runs.
+#[derive(Default)]
+pub struct LedMemory(Mutex<HashMap<String, SavedLed>>);
+
+/// Turns the LED fully off, or back on with the settings it had before.
+///
+/// Off writes brightness 0 with `LED_DIMMABLE` set (the firmware ignores the brightness
+/// otherwise) and `LED_STEADY` cleared, saving the previous values in `memory`. On
+/// restores the saved values. Without any, e.g. after an app restart, the current flags
+/// are kept and the brightness is set to `LED_BRIGHTNESS_MAX` if it is 0.
+pub fn set_led_enabled(
+	state: &PcscState,
+	memory: &LedMemory,
+	on: bool,
+	reader: Option<&str>,
+) -> Result<String, PFError> {
+	log::info!("Turning LED {}", if on { "on" } else { "off" });
+	let (card, select_resp) = connect_for_write(state, reader)?;
+	let serial = select_serial(&select_resp).unwrap_or_default();
+
+	let current = read_phy_raw(&card)?;
+	let (config, _) = parse_phy_tlv(&current);
+	let mut saved = memory.0.lock().unwrap_or_else(|e| e.into_inner());
+
+	let led = if on {
+		saved.get(&serial).copied().unwrap_or(SavedLed {
+			brightness: match config.led_brightness {
+				0 => LED_BRIGHTNESS_MAX,
+				brightness => brightness,
+			},
+			dimmable: config.led_dimmable,
+			steady: config.led_steady,
+		})
+	} else {
+		// Turning off twice must not overwrite the settings from before the first time
+		saved.entry(serial.clone()).or_insert(SavedLed {
+			brightness: config.led_brightness,
+			dimmable: config.led_dimmable,
+			steady: config.led_steady,
+		});
+		SavedLed {
+			brightness: 0,
+			dimmable: true,
+			steady: false,
+		}
+	};
+
+	let input = AppConfigInput {
+		led_brightness: Some(led.brightness),
+		led_dimmable: Some(led.dimmable),
+		led_steady: Some(led.steady),
+		..Default::default()
+	};
+	let tlv = build_config_tlv(&input, &current)?;
+	write_phy_merged(&card, &current, &tlv, &no_progress)?;
+
+	if on {
+		saved.remove(&serial);
+		log::info!("LED on, brightness {}", led.brightness);
+		Ok("LED turned on".into())
+	} else {
+		log::info!("LED off");
+		Ok("LED turned off".into())
+	}
+}
+
+/// Checks whether a reader holds a powered card, without selecting the Rescue Applet.
+///
+/// Tells "no card" apart from "card present but in the wrong mode", which
+/// `read_device_details` both report as a failed connection. A missing card is a result,
+/// not an error, reader problems are still returned as errors.
+pub fn ping_reader(state: &PcscState, reader: Option<&str>) -> Result<ReaderPing, PFError> {
+	let card = match connect_unselected(state, reader) {
+		Ok(card) => card,
+		Err(PFError::Pcsc(pcsc::Error::NoSmartcard | pcsc::Error::RemovedCard)) => {
+			log::info!("Ping: no card in reader");
+			return Ok(ReaderPing {
+				card_present: false,
+				powered: false,
+				atr: None,
+			});
+		}
+		Err(PFError::Pcsc(
+			e @ (pcsc::Error::UnresponsiveCard
+			| pcsc::Error::UnpoweredCard
+			| pcsc::Error::UnsupportedCard),
+		)) => {
+			log::info!("Ping: card present but not answering ({})", e);
+			return Ok(ReaderPing {
+				card_present: true,
+				powered: false,
+				atr: None,
+			});
+		}
+		Err(e) => return Err(e),
+	};
+
+	let powered = card
+		.status2_owned()
+		.map(|status| status.status().contains(pcsc::Status::POWERED))
+		.unwrap_or(false);
+	let atr = card.atr().map(hex::encode_upper);
+	log::info!("Ping: card present, powered: {}, ATR: {:?}", powered, atr);
+
+	Ok(ReaderPing {
+		card_present: true,
+		powered,
+		atr,
+	})
+}
+
+/// Reads health telemetry such as temperature, voltage and error counts.
+///
+/// NOTE: The Rescue Applet has no telemetry read and neither the flash info nor the secure
+/// boot read carry such values, so every field is `None` on current firmware. The device is
+/// still selected, a missing device fails like any other read, but unsupported telemetry
+/// never does, so a harness can poll this uniformly.
+pub fn read_telemetry(state: &PcscState, reader: Option<&str>) -> Result<Telemetry, PFError> {
+	log::info!("Reading device telemetry");
+	let _ = connect_and_select(state, reader)?;
+
+	log::debug!("Firmware reports no telemetry");
+	Ok(Telemetry::default())
+}
+
+/// Reports the reader's largest accepted command and the protocol the card was connected
+/// with, without selecting any applet.
+///
+/// `max_input` comes from `SCARD_ATTR_MAXINPUT`, which not every driver implements.
+pub fn reader_capabilities(
+	state: &PcscState,
+	reader: Option<&str>,
+) -> Result<ReaderCapabilities, PFError> {
+	log::info!("Reading reader capabilities");
+	let card = connect_unselected(state, reader)?;
+
+	let max_input = match card.get_attribute_owned(pcsc::Attribute::Maxinput) {
+		// A DWORD in host byte order
+		Ok(value) => match <[u8; 4]>::try_from(value.as_slice()) {
+			Ok(bytes) => Some(u32::from_ne_bytes(bytes)),
+			Err(_) => {
+				log::warn!(
+					"Unexpected MAXINPUT attribute: {}",
+					hex::encode_upper(&value)
+				);
+				None
+			}
+		},
+		Err(e) => {
+			log::debug!("Reader doesn't report MAXINPUT: {}", e);
+			None
+		}
+	};
+
+	let protocol = card
+		.status2_owned()
+		.ok()
+		.and_then(|status| status.protocol2())
+		.map(|protocol| match protocol {
+			pcsc::Protocol::T0 => "T=0",
+			pcsc::Protocol::T1 => "T=1",
+			pcsc::Protocol::RAW => "RAW",
+		})
+		.map(String::from);
+
+	// Header, Lc, data and Le of the largest short APDU
+	let short_apdu_len = 4 + 1 + APDU_SHORT_MAX_DATA + 1;
+	let extended_apdus = max_input.map(|len| len as usize > short_apdu_len);
+	log::info!(
+		"Reader capabilities: max input {:?}, protocol {:?}",
+		max_input,
+		protocol
+	);
+
+	Ok(ReaderCapabilities {
+		max_input,
+		protocol,
+		extended_apdus,
+	})
+}
+
+/// Reads the card's ATR and decodes its historical bytes.
+///
+/// Doesn't select any applet, so it also works on cards without the Rescue Applet.
+pub fn read_atr(state: &PcscState, reader: Option<&str>) -> Result<AtrInfo, PFError> {
+	log::info!("Reading card ATR");
+	let card = connect_unselected(state, reader)?;
+
+	let Some(atr) = card.atr() else {
+		return Err(PFError::Device("Failed to read the card ATR".into()));
+	};
+	log::debug!("ATR: {}", hex::encode_upper(&atr));
+
+	let historical = atr_historical_bytes(&atr);
+	if historical.is_none() {
+		log::warn!("ATR is shorter than its interface bytes announce");
+	}
+
+	Ok(AtrInfo {
+		atr: hex::encode_upper(&atr),
+		historical_bytes: historical.map(hex::encode_upper),
+		// Many tokens put a readable name here, skip the category indicator byte
+		historical_text: historical
+			.and_then(|bytes| bytes.get(1..))
+			.filter(|text| {
+				!text.is_empty() && text.iter().all(|b| b.is_ascii_graphic() || *b == b' ')
+			})
+			.map(|text| String::from_utf8_lossy(text).into_owned()),
+	})
+}
+
+/// Historical bytes of an ISO 7816-3 ATR (`TS T0 [interface bytes] [historical] [TCK]`),
+/// `None` if the ATR is too short for what `T0` and the `TDi` bytes announce.
+fn atr_historical_bytes(atr: &[u8]) -> Option<&[u8]> {
+	let t0 = *atr.get(1)?;
+	let count = (t0 & 0x0F) as usize;
+
+	// Each Y nibble flags which of TAi, TBi, TCi, TDi follow, TDi carries the next Y
+	let mut y = t0 >> 4;
+	let mut i = 2;
+	while y != 0 {
+		let td = (y & 0x08 != 0).then(|| i + (y & 0x07).count_ones() as usize);
+		i += y.count_ones() as usize;
+		y = match td {
+			Some(td) => atr.get(td)? >> 4,
+			None => 0,
+		};
+	}
+
+	atr.get(i..i + count)
+}
+
+/// Finds the Rescue Applet instructions the firmware handles.
+///
+/// The applet has no query listing its instructions, so each one in `INSTRUCTION_PROBES`
+/// is sent with parameters it rejects before acting, and anything but `6D00` (instruction
+/// not supported) counts as supported.
+pub fn read_instructions(
+	state: &PcscState,
+	reader: Option<&str>,
+) -> Result<InstructionSupport, PFError> {
+	log::info!("Probing supported instructions");
+	let (card, _) = connect_and_select(state, reader)?;
+
+	let mut supported = Vec::new();
+	for &(ins, p1) in INSTRUCTION_PROBES {
+		let rx = transmit(&card, &[APDU_CLA_PROPRIETARY, ins as u8, p1, 0x00, 0x00])?;
+		if rx.ends_with(&SW_INS_NOT_SUPPORTED) {
+			log::debug!("Instruction {:?} (0x{:02X}) not supported", ins, ins as u8);
+		} else {
+			supported.push(ins as u8);
+		}
+	}
+
+	Ok(InstructionSupport {
+		supported,
+		unprobed: UNPROBED_INSTRUCTIONS.iter().map(|&ins| ins as u8).collect(),
+	})
+}
+
+/// Collects what the connected firmware supports from the select response, the secure
+/// boot read and the PHY config into one manifest, for tailoring writes up front.
+pub fn read_capabilities(state: &PcscState, reader: Option<&str>) -> Result<Capabilities, PFError> {
+	log::info!("Reading device capabilities");
+	let (card, select_resp) = connect_and_select(state, reader)?;
+
+	let firmware = select_version(&select_resp);
+	let mcu = select_resp.first().copied().unwrap_or(0);
+	let algorithm = SecureBootAlgorithm::for_mcu(mcu);
+
+	let secure_boot = match read_secure_boot_raw(&card) {
+		Ok(_) => algorithm != SecureBootAlgorithm::Unknown,
+		Err(e @ PFError::Pcsc(_)) => return Err(e),
+		Err(e) => {
+			log::debug!("Secure boot read failed, reporting no secure boot: {}", e);
+			false
+		}
+	};
+
+	let phy = read_phy_raw(&card)?;
+	// The tag is only present when the firmware knows about selectable drivers
+	let led_drivers = if find_tlv(&phy, PhyTag::LedDriver).is_some() {
+		LedDriver::KNOWN.to_vec()
+	} else {
+		Vec::new()
+	};
+
+	Ok(Capabilities {
+		firmware,
+		mcu_name: PicoMcu::describe(mcu),
+		config_writes: firmware.is_some_and(|v| v >= MIN_WRITE_FIRMWARE_VERSION),
+		secure_boot,
+		secure_boot_algorithm: algorithm,
+		secure_boot_key_slots: if secure_boot {
+			SECURE_BOOT_KEY_SLOTS
+		} else {
+			0
+		},
+		supported_curves: None,
+		led_drivers,
+		max_product_name_len: USB_PRODUCT_MAX_LEN - 1,
+		pin_required: probe_pin_required(&card)?,
+	})
+}
+
+/// Reads which USB interfaces the firmware enumerates, from `TAG_ENABLED_USB_ITF`.
+///
+/// There is no way to query the live USB descriptors, this is the configured set that
+/// applies after the next boot. Without the tag the firmware enables all interfaces.
+pub fn read_usb_mode(state: &PcscState, reader: Option<&str>) -> Result<UsbMode, PFError> {
+	log::info!("Reading USB interface configuration");
+	let (card, _) = connect_and_select(state, reader)?;
+
+	let phy = read_phy_raw(&card)?;
+	let configured = find_tlv(&phy, PhyTag::UsbInterfaces).and_then(|val| val.first().copied());
+	let itf = configured
+		.map(UsbInterfaces::from_bits_retain)
+		.unwrap_or(UsbInterfaces::all());
+
+	Ok(UsbMode {
+		ccid: itf.contains(UsbInterfaces::CCID),
+		wcid: itf.contains(UsbInterfaces::WCID),
+		hid: itf.contains(UsbInterfaces::HID),
+		keyboard: itf.contains(UsbInterfaces::KEYBOARD),
+		configured: configured.is_some(),
+	})
+}
+
+/// Returns the unparsed PHY config as hex together with its TLV structure.
+///
+/// Malformed TLV doesn't fail the call, the entries parsed so far are returned with
+/// `truncated` set instead.
+pub fn dump_raw_phy(
+	state: &PcscState,
+	slot: Option<u8>,
+	reader: Option<&str>,
+) -> Result<RawPhyDump, PFError> {
+	let slot = slot.unwrap_or(PHY_SLOT_ACTIVE);
+	log::info!("Dumping raw PHY config (slot {})", slot);
+	let (card, _) = connect_and_select(state, reader)?;
+
+	let data = read_phy_slot_raw(&card, slot)?;
+	let (entries, tail) = split_tlv(&data);
+	let truncated = !tail.is_empty();
+
+	if truncated {
+		log::warn!("Raw PHY config has trailing bytes that aren't valid TLV");
+	}
+
+	Ok(RawPhyDump {
+		hex: hex::encode_upper(&data),
+		entries: entries
+			.into_iter()
+			.map(|(tag, val)| RawTlvEntry {
+				tag,
+				len: val.len() as u16,
+				value_hex: hex::encode_upper(val),
+			})
+			.collect(),
+		truncated,
+	})
+}
+
+/// Turns the CCID (smart card) interface on or off, keeping the other interfaces as set.
+///
+/// With CCID off the device no longer shows up as a card reader, and this tool can't
+/// reach it again until its config is reset. Turning it off is refused when no other
+/// interface would be left enabled.
+pub fn set_ccid_enabled(
+	state: &PcscState,
+	on: bool,
+	reader: Option<&str>,
+) -> Result<WriteResult, PFError> {
+	log::info!("Turning CCID interface {}", if on { "on" } else { "off" });
+	let (mut card, _) = connect_for_write(state, reader)?;
+
+	let current = read_phy_raw(&card)?;
+	let mut itf = find_tlv(&current, PhyTag::UsbInterfaces)
+		.and_then(|val| val.first().copied())
+		.map(UsbInterfaces::from_bits_retain)
+		.unwrap_or(UsbInterfaces::all());
+	if itf.contains(UsbInterfaces::CCID) == on {
+		return Ok(WriteResult::no_changes());
+	}
+
+	itf.set(UsbInterfaces::CCID, on);
+	if !itf.intersects(UsbInterfaces::all()) {
+		log::error!("Refusing to disable CCID, no USB interface would be left");
+		return Err(PFError::Device(
+			"Disabling CCID would leave no USB interface enabled".into(),
+		));
+	}
+
+	let mut tlv = Vec::new();
+	push_tlv_u8(&mut tlv, PhyTag::UsbInterfaces, itf.bits())?;
+	// The device re-enumerates with the new interfaces
+	card.set_disposition(Disposition::ResetCard);
+	write_phy_merged(&card, &current, &tlv, &no_progress)?;
+
+	let message = if on {
+		"CCID interface enabled".to_string()
+	} else {
+		log::warn!("CCID disabled, the device won't be reachable as a card reader");
+		"CCID interface disabled. The device no longer shows up as a card reader, so \
+		 PicoForge can't reconnect to it until its config is reset."
+			.to_string()
+	};
+
+	Ok(WriteResult {
+		applied: true,
+		tags_written: vec![PhyTag::UsbInterfaces as u8],
+		verified: false,
+		message,
+	})
+}
+
+/// Reads every boot key slot up to `SECURE_BOOT_KEY_SLOTS`.
+///
+/// Enumeration stops early once the firmware rejects a slot index as an invalid parameter,
+/// other failures are reported on the slot they happened on.
+pub fn list_secure_boot_keys(
+	state: &PcscState,
+	reader: Option<&str>,
+) -> Result<Vec<SecureBootKeySlot>, PFError> {
+	log::info!("Listing secure boot key slots");
+	let (card, select_resp) = connect_and_select(state, reader)?;
+	let algorithm = SecureBootAlgorithm::for_mcu(select_resp.first().copied().unwrap_or(0));
+
+	let mut slots = Vec::new();
+	for index in 0..SECURE_BOOT_KEY_SLOTS {
+		let slot = match read_secure_boot_slot_raw(&card, index) {
+			Ok(data) => {
+				let key = secure_boot_key_hash(&data);
+				let provisioned = !key.iter().all(|b| *b == 0);
+				SecureBootKeySlot {
+					index,
+					provisioned,
+					algorithm,
+					hash: provisioned.then(|| hex::encode_upper(key)),
+					error: None,
+				}
+			}
+			Err(PFError::StatusWord {
+				sw1: 0x6A,
+				sw2: 0x86,
+			}) => {
+				log::info!("Firmware reports no key slot {}, stopping", index);
+				break;
+			}
+			Err(e @ PFError::Pcsc(_)) => return Err(e),
+			Err(e) => SecureBootKeySlot {
+				index,
+				provisioned: false,
+				algorithm,
+				hash: None,
+				error: Some(e.to_string()),
+			},
+		};
+		slots.push(slot);
+	}
+
+	Ok(slots)
+}
+
+/// Returns the provisioned secure boot key hash as uppercase hex, or `None` if no key is
+/// provisioned (missing or all zeros).
+pub fn read_secure_boot_key_hash(
+	state: &PcscState,
+	reader: Option<&str>,
+) -> Result<Option<String>, PFError> {
+	log::info!("Reading secure boot key hash");
+	let (card, _) = connect_and_select(state, reader)?;
+
+	let data = read_secure_boot_raw(&card)?;
+	let key = secure_boot_key_hash(&data);
+
+	if key.iter().all(|b| *b == 0) {
+		log::info!("No secure boot key provisioned");
+		return Ok(None);
+	}
+
+	Ok(Some(hex::encode_upper(key)))
+}
+
+/// Reads the current device config so it can be saved and re-applied later.
+///
+/// `slot` reads another PHY config slot instead, e.g. a backup partition.
+pub fn export_config(
+	state: &PcscState,
+	slot: Option<u8>,
+	reader: Option<&str>,
+) -> Result<AppConfig, PFError> {
+	let Some(slot) = slot else {
+		log::info!("Exporting device configuration");
+		return Ok(read_device_details(state, reader)?.config);
+	};
+
+	log::info!("Exporting device configuration from PHY slot {}", slot);
+	let (card, _) = connect_and_select(state, reader)?;
+	Ok(parse_phy_tlv(&read_phy_slot_raw(&card, slot)?).0)
+}
+
+/// Compares the device config with `reference`, field by field on their serialized form so
+/// every `AppConfig` field is covered, including the raw opts and curves masks.
+pub fn diff_config(
+	state: &PcscState,
+	reference: &AppConfig,
+	reader: Option<&str>,
+) -> Result<Vec<ConfigDifference>, PFError> {
+	log::info!("Comparing device configuration with a reference");
+	let device = read_device_details(state, reader)?.config;
+
+	let differences = config_differences(reference, &device)?;
+	log::info!("Found {} differing field(s)", differences.len());
+	Ok(differences)
+}
+
+/// Shows what importing `config` would change, without writing anything.
+///
+/// `config` goes through the same validation and merge as `write_config`, each difference
+/// has the current value as `device_value` and the imported one as `reference_value`.
+pub fn preview_import(
+	state: &PcscState,
+	config: &AppConfigInput,
+	reader: Option<&str>,
+) -> Result<Vec<ConfigDifference>, PFError> {
+	log::info!("Previewing configuration import");
+	let (card, select_resp) = connect_and_select(state, reader)?;
+	let current = read_phy_raw(&card)?;
+	let tlv = build_config_tlv(config, &current)?;
+	check_led_gpio(&tlv, &select_resp)?;
+
+	let before = parse_phy_tlv(&current).0;
+	let after = parse_phy_tlv(&merge_phy_tlv(&current, &tlv)?).0;
+	let differences = config_differences(&after, &before)?;
+	log::info!("Import would change {} field(s)", differences.len());
+	Ok(differences)
+}
+
+/// Field by field differences of two configs, compared on their serialized form
+fn config_differences(
+	reference: &AppConfig,
+	device: &AppConfig,
+) -> Result<Vec<ConfigDifference>, PFError> {
+	let to_fields = |config: &AppConfig| match serde_json::to_value(config) {
+		Ok(serde_json::Value::Object(fields)) => Ok(fields),
+		Ok(_) => Err(PFError::Io("Config did not serialize to an object".into())),
+		Err(e) => Err(PFError::Io(format!("Failed to serialize config: {}", e))),
+	};
+	let reference = to_fields(reference)?;
+	let device = to_fields(device)?;
+
+	// Optional fields are left out when unset, so the key sets can differ
+	let mut names: Vec<&String> = reference.keys().collect();
+	names.extend(device.keys().filter(|key| !reference.contains_key(*key)));
+
+	Ok(names
+		.into_iter()
+		.filter_map(|field| {
+			let reference_value = reference.get(field).cloned().unwrap_or_default();
+			let device_value = device.get(field).cloned().unwrap_or_default();
+			(reference_value != device_value).then(|| ConfigDifference {
+				field: field.clone(),
+				reference_value,
+				device_value,
+			})
+		})
+		.collect())
+}
+
+/// Applies a previously exported config, rejecting exports from an unknown schema version.
+///
+/// `config` must not carry the `EXPORT_ONLY_FIELDS` of the export, `AppConfigInput`
+/// rejects them like any unknown field. `import_config_from_file` takes a whole export.
+pub fn import_config(
+	state: &PcscState,
+	config: AppConfigInput,
+	schema_version: Option<u32>,
+	reader: Option<&str>,
+) -> Result<WriteResult, PFError> {
+	if let Some(version) = schema_version.filter(|v| *v != CONFIG_SCHEMA_VERSION) {
+		log::error!("Unsupported config schema version: {}", version);
+		return Err(PFError::Io(format!(
+			"Unsupported config schema version {} (expected {})",
+			version, CONFIG_SCHEMA_VERSION
+		)));
+	}
+
+	log::info!("Importing device configuration");
+	write_config(
+		state,
+		config,
+		None,
+		false,
+		false,
+		false,
+		reader,
+		&no_progress,
+	)
+}
+
+/// Checks a config against an organisational `policy` and lists every violation.
+///
+/// Without `proposed` the device's current config is checked, e.g. to confirm a write.
+/// With it the config the device would have after `write_config(proposed)` is checked,
+/// nothing is written either way.
+pub fn validate_against_policy(
+	state: &PcscState,
+	policy: &Policy,
+	proposed: Option<&AppConfigInput>,
+	reader: Option<&str>,
+) -> Result<Vec<PolicyViolation>, PFError> {
+	let known_curves = RescueCurves::all().names();
+	if let Some(name) = policy
+		.required_curves
+		.iter()
+		.chain(&policy.disabled_curves)
+		.find(|name| !known_curves.contains(&name.to_ascii_lowercase()))
+	{
+		return Err(PFError::Io(format!("Unknown curve in policy: {}", name)));
+	}
+
+	log::info!("Checking configuration against policy");
+	let (card, _) = connect_and_select(state, reader)?;
+	let current = read_phy_raw(&card)?;
+	let config = match proposed {
+		Some(proposed) => {
+			let tlv = build_config_tlv(proposed, &current)?;
+			parse_phy_tlv(&merge_phy_tlv(&current, &tlv)?).0
+		}
+		None => parse_phy_tlv(&current).0,
+	};
+
+	let mut violations = Vec::new();
+	let mut violation = |constraint: &str, expected: String, actual: String| {
+		log::warn!(
+			"Policy violation: {} expected {}, is {}",
+			constraint,
+			expected,
+			actual
+		);
+		violations.push(PolicyViolation {
+			constraint: constraint.into(),
+			expected,
+			actual,
+		});
+	};
+
+	for (constraint, wanted, actual) in [
+		("vid", &policy.vid, &config.vid),
+		("pid", &policy.pid, &config.pid),
+	] {
+		if let Some(wanted) = wanted
+			.as_deref()
+			.map(str::trim)
+			.filter(|wanted| !wanted.eq_ignore_ascii_case(actual))
+		{
+			violation(constraint, wanted.to_uppercase(), actual.clone());
+		}
+	}
+
+	if let Some(min) = policy
+		.min_touch_timeout
+		.filter(|&min| config.touch_timeout < min)
+	{
+		violation(
+			"minTouchTimeout",
+			format!(">= {}", min),
+			config.touch_timeout.to_string(),
+		);
+	}
+
+	if let Some(max) = policy
+		.max_led_brightness
+		.filter(|&max| config.led_brightness > max)
+	{
+		violation(
+			"maxLedBrightness",
+			format!("<= {}", max),
+			config.led_brightness.to_string(),
+		);
+	}
+
+	if let Some(wanted) = policy
+		.power_cycle_on_reset
+		.filter(|&wanted| config.power_cycle_on_reset != wanted)
+	{
+		violation(
+			"powerCycleOnReset",
+			wanted.to_string(),
+			config.power_cycle_on_reset.to_string(),
+		);
+	}
+
+	if let Some(wanted) = policy.ccid_enabled {
+		let actual = config.ccid_enabled.unwrap_or(true);
+		if actual != wanted {
+			violation("ccidEnabled", wanted.to_string(), actual.to_string());
+		}
+	}
+
+	let enabled = RescueCurves::from_bits_truncate(config.curves_raw).names();
+	for name in &policy.required_curves {
+		if !enabled.contains(&name.to_ascii_lowercase()) {
+			violation(
+				"requiredCurves",
+				format!("{} enabled", name),
+				"disabled".into(),
+			);
+		}
+	}
+	for name in &policy.disabled_curves {
+		if enabled.contains(&name.to_ascii_lowercase()) {
+			violation(
+				"disabledCurves",
+				format!("{} disabled", name),
+				"enabled".into(),
+			);
+		}
+	}
+
+	log::info!("Policy check found {} violation(s)", violations.len());
+	Ok(violations)
+}
+
+/// `AppConfig` fields `export_config` writes that can't be written back, skipped on import
+const EXPORT_ONLY_FIELDS: &[&str] = &["optsRaw", "curvesRaw", "ccidEnabled"];
+
+/// Applies a config file from disk, either an `export_config` result or a hand written
+/// profile with just some of the `AppConfigInput` fields.
+///
+/// An optional `schemaVersion` key is checked like in `import_config`. With `only` set just
+/// those fields are applied, `touchTimeout` brings `allowTouchDisable` along and `ledGpio`
+/// brings `ledPinLabel`.
+pub fn import_config_from_file(
+	state: &PcscState,
+	path: &std::path::Path,
+	only: Option<&[String]>,
+	reader: Option<&str>,
+) -> Result<WriteResult, PFError> {
+	log::info!("Importing device configuration from {}", path.display());
+
+	let fields = read_config_file(path)?;
+	let (config, schema_version) = parse_config_fields(fields, &path.display().to_string(), only)?;
+	import_config(state, config, schema_version, reader)
+}
+
+/// Reads a JSON config file into its top level fields
+fn read_config_file(
+	path: &std::path::Path,
+) -> Result<serde_json::Map<String, serde_json::Value>, PFError> {
+	let text = std::fs::read_to_string(path).map_err(|e| match e.kind() {
+		std::io::ErrorKind::NotFound => {
+			PFError::Io(format!("Config file not found: {}", path.display()))
+		}
+		_ => PFError::Io(format!("Failed to read {}: {}", path.display(), e)),
+	})?;
+
+	serde_json::from_str(&text)
+		.map_err(|e| PFError::Io(format!("Invalid JSON in {}: {}", path.display(), e)))
+}
+
+/// Serializes the device config as a TOML profile with a `schemaVersion` key, for
+/// `import_config_toml`. Keys use the same camelCase names as the JSON export.
+pub fn export_config_toml(state: &PcscState, reader: Option<&str>) -> Result<String, PFError> {
+	let config = export_config(state, None, reader)?;
+
+	let mut profile = toml::Table::try_from(&config)
+		.map_err(|e| PFError::Io(format!("Failed to convert config to TOML: {}", e)))?;
+	profile.insert(
+		"schemaVersion".into(),
+		toml::Value::Integer(CONFIG_SCHEMA_VERSION.into()),
+	);
+
+	toml::to_string(&profile).map_err(|e| PFError::Io(format!("Failed to write TOML: {}", e)))
+}
+
+/// Applies a TOML profile, either from `export_config_toml` or hand written.
+///
+/// The profile goes through the same field checks as a JSON file in
+/// `import_config_from_file`, so both formats produce the same write.
+pub fn import_config_toml(
+	state: &PcscState,
+	profile: &str,
+	reader: Option<&str>,
+) -> Result<WriteResult, PFError> {
+	log::info!("Importing device configuration from TOML");
+
+	// Straight into JSON values, so the checks below are the ones JSON files go through
+	let fields =
+		toml::from_str(profile).map_err(|e| PFError::Io(format!("Invalid TOML profile: {}", e)))?;
+
+	let (config, schema_version) = parse_config_fields(fields, "TOML profile", None)?;
+	import_config(state, config, schema_version, reader)
+}
+
+/// Whether `name` is the JSON name of an `AppConfigInput` field
+fn is_input_field(name: &str) -> bool {
+	let probe = serde_json::Map::from_iter([(name.to_owned(), serde_json::Value::Null)]);
+	serde_json::from_value::<AppConfigInput>(probe.into()).is_ok()
+}
 
-	let mut rx_buf = [0; 256];
-	let rx = card.transmit(&apdu, &mut rx_buf)?;
+/// Shared part of the config file imports, turns the top level fields of a file into the
+/// config to write and its `schemaVersion`. `source` names the input in errors.
+fn parse_config_fields(
+	mut fields: serde_json::Map<String, serde_json::Value>,
+	source: &str,
+	only: Option<&[String]>,
+) -> Result<(AppConfigInput, Option<u32>), PFError> {
+	let schema_version = match fields.remove("schemaVersion") {
+		Some(version) => Some(
+			serde_json::from_value(version)
+				.map_err(|e| PFError::Io(format!("Invalid schemaVersion: {}", e)))?,
+		),
+		None => None,
+	};
+	fields.retain(|key, _| !EXPORT_ONLY_FIELDS.contains(&key.as_str()));
 
-	// Check Success (0x90 0x00)
-	if !rx.ends_with(&[0x90, 0x00]) {
-		log::error!("Rescue Applet not found on the device!");
-		return Err(PFError::Device(
-			// There is no such mode as fido, i tink the rescue applet stays active and at the same time fido mode works?
-			// Need to study this more.
-			"Rescue Applet not found on device. Is it in FIDO mode?".into(),
-		));
+	let parse = |fields| {
+		serde_json::from_value::<AppConfigInput>(serde_json::Value::Object(fields))
+			.map_err(|e| PFError::Io(format!("Invalid config in {}: {}", source, e)))
+	};
+	// Checked on the whole file first, so a filter doesn't hide unknown fields
+	let config = parse(fields.clone())?;
+
+	let Some(only) = only else {
+		return Ok((config, schema_version));
+	};
+	if let Some(bad) = only.iter().find(|f| !is_input_field(f)) {
+		return Err(PFError::Io(format!(
+			"Unknown field in only filter: {}",
+			bad
+		)));
 	}
+	let selected = |key: &str| {
+		only.iter().any(|f| {
+			f == key
+				|| (f == "touchTimeout" && key == "allowTouchDisable")
+				|| (f == "ledGpio" && key == "ledPinLabel")
+		})
+	};
+	fields.retain(|key, _| selected(key));
+	log::debug!("Applying only: {:?}", fields.keys().collect::<Vec<_>>());
 
-	log::info!("Successfully connected to Rescue Applet");
-	Ok((card, rx.to_vec()))
+	Ok((parse(fields)?, schema_version))
 }
 
-pub fn read_device_details() -> Result<FullDeviceStatus, PFError> {
-	log::info!("Reading full device details");
-	let (card, select_resp) = connect_and_select()?;
+/// Verifies the rescue PIN and keeps it for the session, so later writes (which each open
+/// a new connection) can re-verify without asking again.
+///
+/// Only needed on firmware that reports `pin_required`. A rejected PIN clears the kept one.
+pub fn verify_pin(state: &PcscState, pin: &str, reader: Option<&str>) -> Result<String, PFError> {
+	log::info!("Verifying rescue PIN");
+	if pin.is_empty() || pin.len() > APDU_SHORT_MAX_DATA {
+		return Err(PFError::ValueOutOfRange {
+			field: "pin".into(),
+			value: pin.len() as u32,
+			min: 1,
+			max: APDU_SHORT_MAX_DATA as u32,
+		});
+	}
 
-	log::info!("Select Response: {:?}", select_resp);
+	let (card, _) = connect_and_select(state, reader)?;
+	let result = send_verify(&card, pin);
 
-	// FIX: Relax the length check.
-	// Minimum valid response is 4 bytes data + 2 bytes SW = 6 bytes.
-	if select_resp.len() < 6 {
-		log::error!("Invalid select response length: {}", select_resp.len());
-		return Err(PFError::Device("Invalid select response".into()));
-	}
+	*state.pin.lock().unwrap_or_else(|e| e.into_inner()) = result.is_ok().then(|| pin.into());
+	result?;
 
-	let version_major = select_resp[2];
-	let version_minor = select_resp[3];
+	log::info!("PIN verified");
+	Ok("PIN verified".into())
+}
 
-	// FIX: Handle missing Serial Number safely
-	// If the firmware sends 14 bytes, we have a serial. If it sends 6, we don't.
-	let serial_str = if select_resp.len() >= 14 {
-		hex::encode_upper(&select_resp[4..12])
-	} else {
-		log::warn!(
-			"Device did not return a Serial Number (Firmware mismatch?). Using placeholder."
-		);
-		"00000000".to_string()
+/// Returns whether the device power cycles on reset, i.e. `DISABLE_POWER_RESET` is clear
+pub fn get_power_reset(state: &PcscState, reader: Option<&str>) -> Result<bool, PFError> {
+	let (card, _) = connect_and_select(state, reader)?;
+	let enabled = read_phy_config(&card)?.power_cycle_on_reset;
+	log::info!("Power cycle on reset: {}", enabled);
+	Ok(enabled)
+}
+
+/// Turns power cycle on reset on or off. Only the `DISABLE_POWER_RESET` bit of the options
+/// is changed, the other flags and tags are kept, and the write is verified.
+pub fn set_power_reset(
+	state: &PcscState,
+	enabled: bool,
+	reader: Option<&str>,
+) -> Result<String, PFError> {
+	log::info!("Setting power cycle on reset to {}", enabled);
+	let config = AppConfigInput {
+		power_cycle_on_reset: Some(enabled),
+		..Default::default()
 	};
+	write_config(
+		state,
+		config,
+		None,
+		true,
+		false,
+		false,
+		reader,
+		&no_progress,
+	)?;
 
-	log::info!("Device Version: {}.{}", version_major, version_minor);
-	log::info!("Device Serial: {}", serial_str);
+	Ok(format!(
+		"Power cycle on reset {}",
+		if enabled { "enabled" } else { "disabled" }
+	))
+}
 
-	// 2. Read Flash Info
-	let mut rx_buf = [0; 256];
-	let rx_flash = card.transmit(
-		&[
-			APDU_CLA_PROPRIETARY,
-			RescueInstruction::Read as u8,
-			ReadParam::FlashInfo as u8,
-			P2_UNUSED,
-			0x00, // Le
-		],
-		&mut rx_buf,
+/// Writes the factory VID/PID (`FACTORY_USB_VID` / `FACTORY_USB_PID`) and leaves every
+/// other setting alone, a lighter alternative to `factory_reset`. The write is verified.
+pub fn reset_vidpid(state: &PcscState, reader: Option<&str>) -> Result<String, PFError> {
+	log::info!(
+		"Resetting VID/PID to factory {:04X}:{:04X}",
+		FACTORY_USB_VID,
+		FACTORY_USB_PID
+	);
+	let config = AppConfigInput {
+		vid: Some(format!("{:04X}", FACTORY_USB_VID)),
+		pid: Some(format!("{:04X}", FACTORY_USB_PID)),
+		..Default::default()
+	};
+	write_config(
+		state,
+		config,
+		None,
+		true,
+		false,
+		false,
+		reader,
+		&no_progress,
 	)?;
 
-	if !rx_flash.ends_with(&SW_SUCCESS) {
-		return Err(PFError::Device("Failed to read flash".into()));
+	Ok(format!(
+		"VID/PID reset to {:04X}:{:04X}",
+		FACTORY_USB_VID, FACTORY_USB_PID
+	))
+}
+
+/// Wipes the PHY config back to the firmware defaults.
+///
+/// The applet clears its PHY data before parsing a write, so an empty `80 1C 01 00`
+/// leaves every tag unset and the firmware falls back to its built-in defaults.
+pub fn factory_reset(
+	state: &PcscState,
+	reader: Option<&str>,
+) -> Result<FactoryResetResult, PFError> {
+	log::info!("Performing factory reset of device configuration");
+	let (card, _) = connect_for_write(state, reader)?;
+
+	if read_secure_boot_status(&card)?.locked {
+		log::error!("Factory reset refused: Secure Lock is engaged");
+		return Err(PFError::Device(
+			"Cannot factory reset while Secure Lock is engaged.".into(),
+		));
 	}
 
-	let mut rdr = Cursor::new(&rx_flash[..rx_flash.len() - 2]);
-	let _free = rdr.read_u32::<BigEndian>().unwrap_or(0);
-	let used = rdr.read_u32::<BigEndian>().unwrap_or(0);
-	let total = rdr.read_u32::<BigEndian>().unwrap_or(0);
+	// APDU: 80 1C 01 00 (no data)
+	let apdu = [
+		APDU_CLA_PROPRIETARY,
+		RescueInstruction::Write as u8,
+		WriteParam::PhyConfig as u8,
+		P2_UNUSED,
+	];
 
-	// NOTE: captured but currently unused variables
-	let _nfiles = rdr.read_u32::<BigEndian>().unwrap_or(0);
-	let _chip_size = rdr.read_u32::<BigEndian>().unwrap_or(0);
+	let rx = transmit(&card, &apdu)?;
 
-	// --- Read Secure Boot Status ---
-	let rx_secure = card.transmit(
-		&[
-			APDU_CLA_PROPRIETARY,
-			RescueInstruction::Read as u8,
-			ReadParam::SecureBootStatus as u8,
-			P2_UNUSED,
-			0x00,
-		],
-		&mut rx_buf,
-	)?;
+	check_status(&rx, "Factory reset failed").map_err(write_error)?;
 
-	let (sb_enabled, sb_locked) = if rx_secure.ends_with(&[0x90, 0x00]) && rx_secure.len() >= 4 {
-		(rx_secure[0] != 0, rx_secure[1] != 0)
-	} else {
-		(false, false)
-	}; // --- Read PHY Config ---
-	let rx_phy = card.transmit(
-		&[
-			APDU_CLA_PROPRIETARY,
-			RescueInstruction::Read as u8,
-			ReadParam::PhyConfig as u8,
-			0x01,
-			0x00,
-		],
-		&mut rx_buf,
-	)?;
+	let config = read_phy_config(&card)?;
+
+	log::info!("Factory reset completed successfully");
+	Ok(FactoryResetResult {
+		message: "Factory Reset Successful".into(),
+		config,
+	})
+}
 
-	if !rx_phy.ends_with(&[0x90, 0x00]) {
-		return Err(PFError::Device("Failed to read config".into()));
+/// Times `iterations` flash info reads to tell slow or flaky readers apart.
+///
+/// Nothing is written. A read that fails counts as a failure. A read that exceeds
+/// `BENCHMARK_ITERATION_TIMEOUT`, or a card that is removed or reset, also ends the run
+/// with the results so far, so a stalling reader can't run past the command's timeout.
+pub fn benchmark_reader(
+	state: &PcscState,
+	iterations: u32,
+	reader: Option<&str>,
+) -> Result<ReaderBenchmark, PFError> {
+	if !(1..=BENCHMARK_MAX_ITERATIONS).contains(&iterations) {
+		return Err(PFError::ValueOutOfRange {
+			field: "iterations".into(),
+			value: iterations,
+			min: 1,
+			max: BENCHMARK_MAX_ITERATIONS,
+		});
 	}
 
-	// Parse TLV
-	let mut config = AppConfig::default();
-	let data = &rx_phy[..rx_phy.len() - 2];
-	let mut i = 0;
-	while i < data.len() {
-		if i + 2 > data.len() {
-			break;
-		}
-		let tag_byte = data[i];
-		let len = data[i + 1] as usize;
-		i += 2;
-		if i + len > data.len() {
-			break;
-		}
-		let val = &data[i..i + len];
+	log::info!("Benchmarking reader with {} iterations", iterations);
+	let (card, _) = connect_and_select(state, reader)?;
 
-		if let Some(tag) = PhyTag::from_u8(tag_byte) {
-			match tag {
-				PhyTag::VidPid => {
-					if val.len() == 4 {
-						let vid = u16::from_be_bytes([val[0], val[1]]);
-						let pid = u16::from_be_bytes([val[2], val[3]]);
-						config.vid = format!("{:04X}", vid);
-						config.pid = format!("{:04X}", pid);
-					}
-				}
-				PhyTag::LedGpio => {
-					if !val.is_empty() {
-						config.led_gpio = val[0];
-					}
-				}
-				PhyTag::LedBrightness => {
-					if !val.is_empty() {
-						config.led_brightness = val[0];
-					}
-				}
-				PhyTag::PresenceTimeout => {
-					if !val.is_empty() {
-						config.touch_timeout = val[0];
-					}
-				}
-				PhyTag::UsbProduct => {
-					let s = std::str::from_utf8(val)
-						.unwrap_or("")
-						.trim_matches(char::from(0));
-					config.product_name = s.to_string();
-				}
-				PhyTag::Opts => {
-					if val.len() >= 2 {
-						let opts_val = u16::from_be_bytes([val[0], val[1]]);
-						let opts = RescueOptions::from_bits_truncate(opts_val);
-
-						config.led_dimmable = opts.contains(RescueOptions::LED_DIMMABLE);
-						config.power_cycle_on_reset =
-							!opts.contains(RescueOptions::DISABLE_POWER_RESET);
-						config.led_steady = opts.contains(RescueOptions::LED_STEADY);
-					}
-				}
-				PhyTag::Curves => {
-					if val.len() >= 4 {
-						let curves_val = u32::from_be_bytes([val[0], val[1], val[2], val[3]]);
-						let curves = RescueCurves::from_bits_truncate(curves_val);
-						config.enable_secp256k1 = curves.contains(RescueCurves::SECP256K1);
-					}
-				}
-				PhyTag::LedDriver => {
-					if !val.is_empty() {
-						config.led_driver = Some(val[0]);
-					}
-				}
+	let mut samples = Vec::new();
+	let mut failures = 0;
+	let mut completed = 0;
+	let mut aborted = None;
+	for _ in 0..iterations {
+		let start = Instant::now();
+		let result = read_flash_raw(&card);
+		let elapsed = start.elapsed();
+		completed += 1;
+
+		match result {
+			Ok(_) if elapsed <= BENCHMARK_ITERATION_TIMEOUT => {
+				samples.push(elapsed.as_secs_f64() * 1000.0);
+			}
+			Ok(_) => {
+				log::warn!(
+					"Benchmark read took {:?}, stopping after {}",
+					elapsed,
+					completed
+				);
+				failures += 1;
+				aborted = Some(format!(
+					"Read took {} ms, over the {} ms budget",
+					elapsed.as_millis(),
+					BENCHMARK_ITERATION_TIMEOUT.as_millis()
+				));
+				break;
+			}
+			Err(PFError::Pcsc(
+				e @ (pcsc::Error::RemovedCard | pcsc::Error::ResetCard | pcsc::Error::NoSmartcard),
+			)) => {
+				log::warn!("Benchmark aborted after {} iterations: {}", completed, e);
+				failures += 1;
+				aborted = Some(e.to_string());
+				break;
+			}
+			Err(e) => {
+				log::debug!("Benchmark read failed: {}", e);
+				failures += 1;
 			}
 		}
-		i += len;
 	}
 
+	let (min_ms, max_ms, avg_ms, stddev_ms) = if samples.is_empty() {
+		(0.0, 0.0, 0.0, 0.0)
+	} else {
+		let count = samples.len() as f64;
+		let avg = samples.iter().sum::<f64>() / count;
+		let variance = samples.iter().map(|s| (s - avg).powi(2)).sum::<f64>() / count;
+		(
+			samples.iter().copied().fold(f64::INFINITY, f64::min),
+			samples.iter().copied().fold(0.0, f64::max),
+			avg,
+			variance.sqrt(),
+		)
+	};
+
 	log::info!(
-		"Successfully read device details - Serial: {}, Firmware: {}.{}",
-		serial_str,
-		version_major,
-		version_minor
+		"Benchmark: avg {:.2} ms, min {:.2} ms, max {:.2} ms, {} failure(s)",
+		avg_ms,
+		min_ms,
+		max_ms,
+		failures
 	);
-
-	Ok(FullDeviceStatus {
-		info: DeviceInfo {
-			serial: serial_str,
-			flash_used: used / 1024,
-			flash_total: total / 1024,
-			firmware_version: format!("{}.{}", version_major, version_minor),
-		},
-		config,
-		secure_boot: sb_enabled,
-		secure_lock: sb_locked,
+	Ok(ReaderBenchmark {
+		iterations,
+		completed,
+		failures,
+		min_ms,
+		max_ms,
+		avg_ms,
+		stddev_ms,
+		aborted,
 	})
 }
 
-pub fn write_config(config: AppConfigInput) -> Result<String, PFError> {
-	log::info!("Writing configuration to device");
-	log::debug!("Config input: {:?}", config);
+/// Non-destructive health check of the rescue applet.
+///
+/// Reads flash info and secure boot status, then writes the current PHY config back
+/// unchanged and checks it reads back byte for byte. If the read back differs the
+/// original config is written again.
+pub fn self_test(state: &PcscState, reader: Option<&str>) -> Result<SelfTestReport, PFError> {
+	log::info!("Running device self test");
+	let (card, _) = connect_for_write(state, reader)?;
+	let mut checks = Vec::new();
 
-	// 1. Construct TLV Blob
-	let mut tlv = Vec::new();
+	let secure_boot = read_secure_boot_raw(&card);
+	let locked = secure_boot
+		.as_ref()
+		.is_ok_and(|data| data.get(1).is_some_and(|b| *b != 0));
+	if locked {
+		log::error!("Self test refused: Secure Lock is engaged");
+		return Err(PFError::Device(
+			"Cannot run self test while Secure Lock is engaged.".into(),
+		));
+	}
+	checks.push(SelfTestCheck::from_result(
+		"secureBootStatus",
+		secure_boot.map(|data| format!("{} bytes", data.len())),
+	));
 
-	// VID:PID (Tag 0x00)
-	if let (Some(vid_str), Some(pid_str)) = (&config.vid, &config.pid) {
-		let vid =
-			u16::from_str_radix(vid_str, 16).map_err(|_| PFError::Io("Invalid VID".into()))?;
-		let pid =
-			u16::from_str_radix(pid_str, 16).map_err(|_| PFError::Io("Invalid PID".into()))?;
+	let flash = read_flash_raw(&card).and_then(|data| match data.len() {
+		n if n >= 12 => Ok(format!("{} bytes", n)),
+		n => Err(PFError::Device(format!(
+			"Flash info too short ({} bytes)",
+			n
+		))),
+	});
+	checks.push(SelfTestCheck::from_result("flashInfo", flash));
 
-		tlv.push(PhyTag::VidPid as u8);
-		tlv.push(0x04);
-		tlv.write_u16::<BigEndian>(vid).unwrap();
-		tlv.write_u16::<BigEndian>(pid).unwrap();
-	}
+	checks.push(SelfTestCheck::from_result(
+		"phyConfig",
+		phy_round_trip(&card),
+	));
 
-	// LED GPIO (Tag 0x04)
-	if let Some(val) = config.led_gpio {
-		tlv.push(PhyTag::LedGpio as u8);
-		tlv.push(0x01);
-		tlv.push(val);
-	}
+	let passed = checks.iter().all(|c| c.passed);
+	log::info!("Self test finished, passed: {}", passed);
+	Ok(SelfTestReport { passed, checks })
+}
 
-	// LED Brightness (Tag 0x05)
-	if let Some(val) = config.led_brightness {
-		tlv.push(PhyTag::LedBrightness as u8);
-		tlv.push(0x01);
-		tlv.push(val);
-	}
+/// Writes the current PHY config back as is and confirms the device returns the same bytes
+fn phy_round_trip(card: &dyn ApduTransport) -> Result<String, PFError> {
+	let original = read_phy_raw(card)?;
 
-	// Touch Timeout (Tag 0x08)
-	if let Some(val) = config.touch_timeout {
-		tlv.push(PhyTag::PresenceTimeout as u8);
-		tlv.push(0x01);
-		tlv.push(val);
-	}
+	let result = write_phy_raw(card, &original)
+		.and_then(|_| read_phy_raw(card))
+		.and_then(|readback| {
+			if readback == original {
+				return Ok(format!("{} bytes round-tripped", original.len()));
+			}
+			log::error!(
+				"PHY round trip mismatch: wrote {}, read {}",
+				hex::encode_upper(&original),
+				hex::encode_upper(&readback)
+			);
+			Err(PFError::Device(
+				"PHY config read back differs from what was written".into(),
+			))
+		});
 
-	// Options
-	if let (Some(dim), Some(cycle), Some(steady)) = (
-		config.led_dimmable,
-		config.power_cycle_on_reset,
-		config.led_steady,
-	) {
-		let mut opts = RescueOptions::empty();
-		if dim {
-			opts.insert(RescueOptions::LED_DIMMABLE);
-		}
-		if !cycle {
-			opts.insert(RescueOptions::DISABLE_POWER_RESET);
-		}
-		if steady {
-			opts.insert(RescueOptions::LED_STEADY);
+	// A failed chained write or mismatching read back may have left a different config behind
+	if result.is_err() {
+		log::warn!("PHY round trip failed, restoring original config");
+		if let Err(e) = write_phy_raw(card, &original) {
+			log::error!("Failed to restore PHY config after self test: {}", e);
 		}
-
-		tlv.push(PhyTag::Opts as u8);
-		tlv.push(0x02);
-		tlv.write_u16::<BigEndian>(opts.bits()).unwrap();
 	}
 
-	// Curves
-	if let Some(enabled) = config.enable_secp256k1 {
-		let mut curves = RescueCurves::empty();
-		if enabled {
-			curves.insert(RescueCurves::SECP256K1);
-		}
+	result
+}
 
-		tlv.push(PhyTag::Curves as u8);
-		tlv.push(0x04);
-		tlv.write_u32::<BigEndian>(curves.bits()).unwrap();
-	}
+/// Sends a raw APDU given as hex (whitespace allowed) to the selected Rescue Applet and
+/// returns the unprocessed response as hex, including the status word.
+#[cfg(feature = "debug_apdu")]
+pub fn send_apdu(
+	state: &PcscState,
+	apdu_hex: &str,
+	reader: Option<&str>,
+) -> Result<String, PFError> {
+	let cleaned: String = apdu_hex.split_whitespace().collect();
+	let apdu = hex::decode(&cleaned).map_err(|e| PFError::InvalidHex {
+		field: "apdu".into(),
+		reason: format!("'{}': {}", apdu_hex, e),
+	})?;
 
-	// LED Driver (Tag 0x0C)
-	if let Some(val) = config.led_driver {
-		tlv.push(PhyTag::LedDriver as u8);
-		tlv.push(0x01);
-		tlv.push(val);
+	if apdu.len() < 4 {
+		return Err(PFError::Io(format!(
+			"APDU must be at least 4 bytes (CLA INS P1 P2), got {}",
+			apdu.len()
+		)));
 	}
 
-	// Product Name (Tag 0x09)
-	if let Some(name) = config.product_name {
-		if !name.is_empty() {
-			let name_bytes = name.as_bytes();
-			let len = name_bytes.len() + 1;
-			if len > 32 {
-				return Err(PFError::Io("Product name too long".into()));
-			}
+	let (card, _) = connect_and_select(state, reader)?;
 
-			tlv.push(PhyTag::UsbProduct as u8);
-			tlv.push(len as u8);
-			tlv.extend_from_slice(name_bytes);
-			tlv.push(0x00); // Null terminator
-		}
-	}
+	// Same logging as every other APDU, payloads (product name, serial, PIN) never at debug
+	log_apdu(&apdu);
+	let rx = transport::transmit_card(&card, &apdu)?;
+	log_response(&rx);
 
-	// 2. Connect and Send
-	if tlv.is_empty() {
-		log::warn!("No configuration changes to apply");
-		return Ok("No changes to apply".into());
-	}
+	Ok(hex::encode_upper(rx))
+}
 
-	log::debug!("TLV payload size: {} bytes", tlv.len());
+/// Writes a hand-crafted PHY TLV as the complete config, the write-side counterpart of
+/// `dump_raw_phy`. Debug only, like `send_apdu`.
+///
+/// The blob must be well-formed TLV with every known tag long enough to parse. Payloads
+/// that leave the device in an odd state (empty config, empty product name, zero VID/PID)
+/// are refused unless `force` is set.
+#[cfg(feature = "debug_apdu")]
+pub fn write_raw_phy(
+	state: &PcscState,
+	tlv_hex: &str,
+	force: bool,
+	reader: Option<&str>,
+) -> Result<String, PFError> {
+	let cleaned: String = tlv_hex.split_whitespace().collect();
+	let data = hex::decode(&cleaned).map_err(|e| PFError::InvalidHex {
+		field: "hex".into(),
+		reason: format!("'{}': {}", tlv_hex, e),
+	})?;
 
-	let (card, _) = connect_and_select()?;
+	validate_raw_phy(&data, force)?;
 
-	// APDU: 80 1C 01 00 [Lc] [Data]
-	let mut apdu = vec![
-		APDU_CLA_PROPRIETARY,
-		RescueInstruction::Write as u8,
-		WriteParam::PhyConfig as u8,
-		P2_UNUSED,
-		tlv.len() as u8, // Lc
-	];
-	apdu.extend_from_slice(&tlv);
+	let (card, _) = connect_for_write(state, reader)?;
+	log::warn!("Writing raw PHY config ({} bytes)", data.len());
+	write_phy_raw(&card, &data)?;
 
-	let mut rx_buf = [0; 256];
-	let rx = card.transmit(&apdu, &mut rx_buf)?;
+	Ok(format!("Wrote {} bytes of PHY config", data.len()))
+}
 
-	if rx.ends_with(&[0x90, 0x00]) {
-		log::info!("Configuration applied successfully");
-		Ok("Configuration Applied Successfully".into())
-	} else {
-		log::error!("Configuration write failed: {:02X?}", rx);
-		Err(PFError::Device(format!("Write failed: {:02X?}", rx)))
+#[cfg(feature = "debug_apdu")]
+fn validate_raw_phy(data: &[u8], force: bool) -> Result<(), PFError> {
+	let invalid = |reason: String| PFError::InvalidHex {
+		field: "hex".into(),
+		reason,
+	};
+
+	let (entries, tail) = split_tlv(data);
+	if !tail.is_empty() {
+		return Err(invalid(
+			"TLV is truncated, a length runs past the end".into(),
+		));
+	}
+
+	for (i, &(tag, val)) in entries.iter().enumerate() {
+		if entries[..i].iter().any(|(t, _)| *t == tag) {
+			return Err(invalid(format!("tag 0x{:02X} appears more than once", tag)));
+		}
+		if let Some(known) = PhyTag::from_u8(tag).filter(|known| val.len() < known.min_len()) {
+			return Err(invalid(format!(
+				"tag 0x{:02X} ({:?}) needs at least {} bytes, got {}",
+				tag,
+				known,
+				known.min_len(),
+				val.len()
+			)));
+		}
+	}
+
+	if force {
+		return Ok(());
+	}
+
+	let mut dangers = Vec::new();
+	if entries.is_empty() {
+		dangers.push("empty config resets every setting");
+	}
+	if find_tlv(data, PhyTag::UsbProduct).is_some_and(|val| val.first().is_none_or(|b| *b == 0)) {
+		dangers.push("empty product name");
+	}
+	if find_tlv(data, PhyTag::VidPid).is_some_and(|val| val[..4].iter().all(|b| *b == 0)) {
+		dangers.push("VID/PID 0000:0000");
+	}
+
+	if !dangers.is_empty() {
+		log::error!("Refusing raw PHY write: {}", dangers.join(", "));
+		return Err(PFError::Device(format!(
+			"Refusing potentially dangerous payload ({}), set force to write it anyway",
+			dangers.join(", ")
+		)));
 	}
+
+	Ok(())
 }
 
-pub fn reboot_device(to_bootsel: bool) -> Result<String, PFError> {
-	let (card, _) = connect_and_select()?;
+/// Reboots the device, either normally (back into FIDO operation) or into BOOTSEL.
+///
+/// The card drops off the bus as soon as the firmware acts on the command, so a reset or
+/// removal reported by PCSC is treated as success. Once rebooted normally the device
+/// re-enumerates, the frontend should wait for the `device-connected` event before
+/// talking to it again.
+pub fn reboot_device(
+	state: &PcscState,
+	to_bootsel: bool,
+	reader: Option<&str>,
+) -> Result<String, PFError> {
+	log::info!("Rebooting device (bootsel: {})", to_bootsel);
+	let (mut card, _) = connect_and_select(state, reader)?;
+	// The device drops off the bus, resetting it would only race the reboot
+	card.set_disposition(Disposition::LeaveCard);
 
 	let param = if to_bootsel {
 		RebootParam::Bootsel
@@ -382,38 +3203,399 @@ pub fn reboot_device(to_bootsel: bool) -> Result<String, PFError> {
 		0x00,
 	];
 
-	let mut rx_buf = [0; 256];
-	let rx = card.transmit(&apdu, &mut rx_buf)?;
+	match transmit(&card, &apdu) {
+		Ok(rx) => check_status(&rx, "Reboot failed")?,
+		Err(PFError::Pcsc(
+			e @ (pcsc::Error::ResetCard
+			| pcsc::Error::RemovedCard
+			| pcsc::Error::NoSmartcard
+			| pcsc::Error::ReaderUnavailable),
+		)) => {
+			log::info!("Device disconnected while rebooting ({}), as expected", e);
+		}
+		Err(e) => return Err(e),
+	}
+
+	Ok("Reboot command sent".into())
+}
 
-	if rx.ends_with(&SW_SUCCESS) {
-		Ok("Reboot command sent".into())
-	} else {
-		Err(PFError::Device(format!("Reboot failed: {:02X?}", rx)))
+/// Waits for a device to come back after a reboot or USB reconfiguration, returning its
+/// info once the Rescue Applet can be selected again.
+///
+/// Between attempts this blocks on a PCSC status change instead of spinning, so a reader
+/// showing up wakes it right away. Changing the VID/PID renames the reader, when the
+/// requested one has not reappeared only readers that weren't present when the wait
+/// started are tried, so another key already plugged in is never taken for this one.
+/// With `serial` set only the device with that serial is accepted, on whatever reader.
+pub fn wait_for_device(
+	state: &PcscState,
+	timeout: Duration,
+	serial: Option<&str>,
+	reader: Option<&str>,
+) -> Result<DeviceInfo, PFError> {
+	log::info!("Waiting up to {:?} for the device to come back", timeout);
+	let deadline = Instant::now() + timeout;
+
+	// The requested reader may still be listed while the device goes down
+	let before: HashSet<String> = list_readers(state)
+		.unwrap_or_default()
+		.into_iter()
+		.filter(|name| Some(name.as_str()) != reader)
+		.collect();
+
+	// Status changes are watched on a context of their own, like the hotplug watcher, so
+	// a blocked wait never shares a context with the connection attempts
+	let monitor = Context::establish(Scope::User)
+		.inspect_err(|e| log::warn!("No context for status change waits ({}), polling", e))
+		.ok();
+
+	loop {
+		let err = match read_returned_device(state, serial, reader, &before) {
+			Ok(info) => return Ok(info),
+			Err(e) => e,
+		};
+
+		let now = Instant::now();
+		if now >= deadline {
+			log::error!("Device did not come back within {:?}: {}", timeout, err);
+			return Err(PFError::Device(format!(
+				"Device did not come back within {} ms ({})",
+				timeout.as_millis(),
+				err
+			)));
+		}
+		log::debug!("Device not ready yet: {}", err);
+
+		let wait = (deadline - now).min(WAIT_FOR_DEVICE_POLL_INTERVAL);
+		wait_for_reader_change(monitor.as_ref(), wait);
+	}
+}
+
+/// One attempt of `wait_for_device`, reading the device on the readers it may have come
+/// back on. `before` holds the readers present when the wait started.
+fn read_returned_device(
+	state: &PcscState,
+	serial: Option<&str>,
+	reader: Option<&str>,
+	before: &HashSet<String>,
+) -> Result<DeviceInfo, PFError> {
+	let present = list_readers(state)?;
+	let candidates: Vec<Option<&str>> = match (reader, serial) {
+		(Some(name), _) if present.iter().any(|n| n == name) => vec![Some(name)],
+		// The serial identifies the device on whatever reader it came back on
+		(_, Some(_)) => present.iter().map(|n| Some(n.as_str())).collect(),
+		(Some(name), None) => {
+			log::debug!("Reader {:?} not present, trying readers added since", name);
+			present
+				.iter()
+				.filter(|n| !before.contains(*n))
+				.map(|n| Some(n.as_str()))
+				.collect()
+		}
+		(None, None) => vec![None],
+	};
+
+	let wanted = serial.map(|s| s.trim().replace('-', ""));
+	let mut last_err = PFError::NoReader;
+	for target in candidates {
+		match read_device_details(state, target) {
+			Ok(status)
+				if wanted
+					.as_deref()
+					.is_none_or(|wanted| status.info.serial.eq_ignore_ascii_case(wanted)) =>
+			{
+				return Ok(status.info);
+			}
+			Ok(status) => {
+				log::debug!("Reader {:?} hosts serial {}", target, status.info.serial);
+				last_err = PFError::Device(format!(
+					"Found serial {}, waiting for {}",
+					status.info.serial,
+					wanted.as_deref().unwrap_or_default()
+				));
+			}
+			Err(e) => last_err = e,
+		}
+	}
+	Err(last_err)
+}
+
+/// Blocks until PCSC reports a reader or card change on `monitor`, or `timeout` passes.
+/// Without a context, or if the wait fails, it just sleeps for the rest of `timeout`.
+fn wait_for_reader_change(monitor: Option<&Context>, timeout: Duration) {
+	let started = Instant::now();
+	if let Some(ctx) = monitor {
+		match wait_for_status_change(ctx, timeout) {
+			Ok(()) | Err(pcsc::Error::Timeout) => return,
+			Err(e) => log::debug!("Status change wait failed ({}), sleeping instead", e),
+		}
+	}
+	std::thread::sleep(timeout.saturating_sub(started.elapsed()));
+}
+
+fn wait_for_status_change(ctx: &Context, timeout: Duration) -> Result<(), pcsc::Error> {
+	let mut states = vec![ReaderState::new(PNP_NOTIFICATION(), State::UNAWARE)];
+	if let Ok(names) = list_reader_cstrings(ctx) {
+		states.extend(
+			names
+				.into_iter()
+				.map(|name| ReaderState::new(name, State::UNAWARE)),
+		);
+	}
+
+	// The first call only fills in the current state, the second waits for a change
+	match ctx.get_status_change(Some(Duration::ZERO), &mut states) {
+		Ok(()) | Err(pcsc::Error::Timeout) => {}
+		Err(e) => return Err(e),
+	}
+	for rs in &mut states {
+		rs.sync_current_state();
+	}
+
+	ctx.get_status_change(Some(timeout), &mut states)
+}
+
+/// Stop flag for a running `provision_all`, registered as Tauri managed state so the
+/// technician can end a run from another command while it holds the device lock.
+#[derive(Default)]
+pub struct ProvisionControl(AtomicBool);
+
+/// Asks a running `provision_all` to stop once the current device is done
+pub fn stop_provisioning(control: &ProvisionControl) {
+	log::info!("Stopping provisioning after the current device");
+	control.0.store(true, Ordering::Relaxed);
+}
+
+/// Writes `config` to up to `count` devices inserted one after another, returning one
+/// report per device.
+///
+/// Each device is written with verification, pinned to the serial read just before, a
+/// failed write blinks the error pattern so the technician can set it aside. Its serial is
+/// remembered so a device left in the reader (or put back) is not written
+/// again in the same run. Between devices this waits for a PCSC status change, up to
+/// `wait_timeout` for the next unseen device. The run ends early when that wait runs
+/// out or `stop_provisioning` is called; the reports gathered so far are returned.
+///
+/// Firmware without a serial reports `PLACEHOLDER_SERIAL`, which can't tell one device
+/// from the next. Such a device is not written, it gets a failed report and ends the run.
+pub fn provision_all(
+	state: &PcscState,
+	control: &ProvisionControl,
+	config: AppConfigInput,
+	count: usize,
+	wait_timeout: Duration,
+	reader: Option<&str>,
+) -> Result<Vec<ProvisionReport>, PFError> {
+	// Reject a bad config before the first device, not once per device
+	build_config_tlv(&config, &[])?;
+	control.0.store(false, Ordering::Relaxed);
+	log::info!("Provisioning up to {} devices", count);
+
+	let monitor = Context::establish(Scope::User)
+		.inspect_err(|e| log::warn!("No context for status change waits ({}), polling", e))
+		.ok();
+	let mut done: HashSet<String> = HashSet::new();
+	let mut reports = Vec::new();
+
+	while reports.len() < count {
+		let Some(serial) = wait_for_new_device(
+			state,
+			control,
+			monitor.as_ref(),
+			&done,
+			wait_timeout,
+			reader,
+		) else {
+			break;
+		};
+
+		if serial == PLACEHOLDER_SERIAL {
+			log::error!("Device has no serial number, stopping provisioning");
+			reports.push(ProvisionReport {
+				serial,
+				success: false,
+				verified: false,
+				message: "Device reports no serial number, it can't be told apart from the next"
+					.into(),
+			});
+			break;
+		}
+
+		log::info!("Provisioning device {}", serial);
+		let result = write_config(
+			state,
+			config.clone(),
+			Some(&serial),
+			true,
+			true,
+			false,
+			reader,
+			&no_progress,
+		);
+		let report = match result {
+			Ok(res) => ProvisionReport {
+				serial: serial.clone(),
+				success: true,
+				verified: res.verified,
+				message: res.message,
+			},
+			Err(e) => {
+				log::error!("Provisioning device {} failed: {}", serial, e);
+				ProvisionReport {
+					serial: serial.clone(),
+					success: false,
+					verified: false,
+					message: e.to_string(),
+				}
+			}
+		};
+		reports.push(report);
+		done.insert(serial);
+	}
+
+	log::info!(
+		"Provisioning finished, {} of {} devices succeeded",
+		reports.iter().filter(|r| r.success).count(),
+		reports.len()
+	);
+	Ok(reports)
+}
+
+/// Waits for a device whose serial is not in `done`, `None` when stopped or timed out
+fn wait_for_new_device(
+	state: &PcscState,
+	control: &ProvisionControl,
+	monitor: Option<&Context>,
+	done: &HashSet<String>,
+	timeout: Duration,
+	reader: Option<&str>,
+) -> Option<String> {
+	let deadline = Instant::now() + timeout;
+	loop {
+		if control.0.load(Ordering::Relaxed) {
+			log::info!("Provisioning stopped");
+			return None;
+		}
+
+		match read_device_details(state, reader) {
+			Ok(status) if !done.contains(&status.info.serial) => return Some(status.info.serial),
+			Ok(_) => log::debug!("Device already provisioned in this run, waiting for the next"),
+			Err(e) => log::debug!("No device to provision yet: {}", e),
+		}
+
+		let now = Instant::now();
+		if now >= deadline {
+			log::info!("No new device within {:?}, ending provisioning", timeout);
+			return None;
+		}
+		wait_for_reader_change(monitor, (deadline - now).min(WAIT_FOR_DEVICE_POLL_INTERVAL));
 	}
 }
 
+/// Reads how many Secure Lock attempts the firmware still allows.
+///
+/// `None` when the firmware has no such counter, which so far is every known release.
+pub fn read_secure_lock_attempts(
+	state: &PcscState,
+	reader: Option<&str>,
+) -> Result<Option<u8>, PFError> {
+	log::info!("Reading remaining secure lock attempts");
+	let (card, _) = connect_and_select(state, reader)?;
+
+	Ok(read_secure_boot_status(&card)?.lock_attempts)
+}
+
+/// Reports whether the device can still leave its current secure boot state.
+///
+/// Once Secure Lock is engaged the OTP lock bits are set for good, there is no unlock path.
+pub fn can_unlock_secure_boot(
+	state: &PcscState,
+	reader: Option<&str>,
+) -> Result<SecureBootUnlockStatus, PFError> {
+	log::info!("Checking whether secure boot can be unlocked");
+	let (card, _) = connect_and_select(state, reader)?;
+
+	let status = read_secure_boot_status(&card)?;
+	let (can_unlock, reason) = match (status.enabled, status.locked) {
+		(_, true) => (
+			false,
+			"Secure Lock is engaged, the OTP lock can't be reverted",
+		),
+		(true, false) => (
+			true,
+			"Secure boot is enabled but not locked, locking would be permanent",
+		),
+		(false, false) => (true, "Secure boot is not enabled"),
+	};
+
+	Ok(SecureBootUnlockStatus {
+		can_unlock,
+		reason: reason.into(),
+	})
+}
+
 /// UNSTABLE! (WIP)
-pub fn enable_secure_boot(lock: bool) -> Result<String, PFError> {
-	let (card, _) = connect_and_select()?;
+///
+/// `key_index` selects the boot key slot, index 0 is the factory default slot.
+///
+/// Locking burns OTP and can never be undone, so `lock` is only accepted together with
+/// `confirm_irreversible`.
+pub fn enable_secure_boot(
+	state: &PcscState,
+	key_index: u8,
+	lock: bool,
+	confirm_irreversible: bool,
+	reader: Option<&str>,
+) -> Result<WriteResult, PFError> {
+	if lock && !confirm_irreversible {
+		log::error!("Secure lock requested without confirming it is irreversible");
+		return Err(PFError::Io(
+			"Secure Lock is permanent, set confirm_irreversible to proceed".into(),
+		));
+	}
+
+	check_key_index(key_index)?;
+
+	let (card, _) = connect_for_write(state, reader)?;
 
 	// APDU: 80 1D [KeyIndex] [LockBool] 00
-	// KeyIndex = 0 (Default), LockBool = 1 if true
-	let lock_byte = if lock { 0x01 } else { 0x00 };
+	// LockBool = 1 if true
+	let lock_param = if lock {
+		SecureLockParam::Lock
+	} else {
+		SecureLockParam::Unlock
+	};
 
 	let apdu = [
 		APDU_CLA_PROPRIETARY,
 		RescueInstruction::Secure as u8,
-		0x00, // Boot Key Index (0 = Default)
-		lock_byte as u8,
+		key_index,
+		lock_param as u8,
 		0x00,
 	];
 
-	let mut rx_buf = [0; 256];
-	let rx = card.transmit(&apdu, &mut rx_buf)?;
+	let rx = transmit(&card, &apdu)?;
 
-	if rx.ends_with(&[0x90, 0x00]) {
-		Ok("Secure Boot Enabled".into())
-	} else {
-		Err(PFError::Device(format!("Secure Boot failed: {:02X?}", rx)))
+	check_status(&rx, "Secure Boot failed").map_err(write_error)?;
+
+	Ok(WriteResult {
+		applied: true,
+		tags_written: Vec::new(),
+		verified: false,
+		message: "Secure Boot Enabled".into(),
+	})
+}
+
+fn check_key_index(key_index: u8) -> Result<(), PFError> {
+	if key_index < SECURE_BOOT_KEY_SLOTS {
+		return Ok(());
 	}
+
+	log::error!("Invalid secure boot key index: {}", key_index);
+	Err(PFError::ValueOutOfRange {
+		field: "keyIndex".into(),
+		value: key_index.into(),
+		min: 0,
+		max: (SECURE_BOOT_KEY_SLOTS - 1).into(),
+	})
 }
@@ -0,0 +1,838 @@
+//! Unit tests for the Rescue Applet protocol helpers, run against a `MockCard` instead of
+//! a live PCSC connection.
+
+use super::*;
+use std::cell::RefCell;
+
+/// Transport answering each APDU with a canned response, matched by APDU prefix.
+///
+/// The chaining bit of CLA is ignored when matching, so a prefix covers every chunk of a
+/// chained command. APDUs without a matching response get `6D 00`, like an instruction the
+/// firmware doesn't know. Every APDU sent is recorded.
+pub(super) struct MockCard {
+	responses: Vec<(Vec<u8>, Vec<u8>)>,
+	sent: RefCell<Vec<Vec<u8>>>,
+}
+
+impl MockCard {
+	pub(super) fn new() -> Self {
+		Self {
+			responses: Vec::new(),
+			sent: RefCell::new(Vec::new()),
+		}
+	}
+
+	/// Answers APDUs starting with `prefix` with `response`, earlier prefixes win
+	pub(super) fn respond(mut self, prefix: &[u8], response: Vec<u8>) -> Self {
+		self.responses.push((prefix.to_vec(), response));
+		self
+	}
+
+	/// Answers PHY config reads of the active slot with `phy` and acknowledges PHY writes
+	pub(super) fn with_phy(self, phy: &[u8]) -> Self {
+		self.respond(PHY_READ, ok(phy))
+			.respond(PHY_WRITE, SW_SUCCESS.to_vec())
+	}
+
+	pub(super) fn sent(&self) -> Vec<Vec<u8>> {
+		self.sent.borrow().clone()
+	}
+
+	/// Data of every complete PHY config write, chained chunks joined
+	pub(super) fn phy_writes(&self) -> Vec<Vec<u8>> {
+		let mut writes = Vec::new();
+		let mut pending = Vec::new();
+		for apdu in self.sent.borrow().iter() {
+			if !matches_prefix(apdu, PHY_WRITE) {
+				continue;
+			}
+			pending.extend_from_slice(apdu.get(5..).unwrap_or_default());
+			if apdu[0] & APDU_CLA_CHAINING == 0 {
+				writes.push(std::mem::take(&mut pending));
+			}
+		}
+		writes
+	}
+}
+
+impl ApduTransport for MockCard {
+	fn transmit(&self, apdu: &[u8]) -> Result<Vec<u8>, PFError> {
+		self.sent.borrow_mut().push(apdu.to_vec());
+		Ok(self
+			.responses
+			.iter()
+			.find(|(prefix, _)| matches_prefix(apdu, prefix))
+			.map(|(_, response)| response.clone())
+			.unwrap_or_else(|| SW_INS_NOT_SUPPORTED.to_vec()))
+	}
+}
+
+fn matches_prefix(apdu: &[u8], prefix: &[u8]) -> bool {
+	match (apdu.split_first(), prefix.split_first()) {
+		(Some((cla, apdu)), Some((prefix_cla, prefix))) => {
+			cla & !APDU_CLA_CHAINING == *prefix_cla && apdu.starts_with(prefix)
+		}
+		(_, None) => true,
+		(None, Some(_)) => false,
+	}
+}
+
+/// `data` followed by `90 00`
+pub(super) fn ok(data: &[u8]) -> Vec<u8> {
+	[data, &SW_SUCCESS].concat()
+}
+
+pub(super) const PHY_READ: &[u8] = &[
+	APDU_CLA_PROPRIETARY,
+	RescueInstruction::Read as u8,
+	ReadParam::PhyConfig as u8,
+	PHY_SLOT_ACTIVE,
+];
+
+pub(super) const PHY_WRITE: &[u8] = &[
+	APDU_CLA_PROPRIETARY,
+	RescueInstruction::Write as u8,
+	WriteParam::PhyConfig as u8,
+];
+
+#[test]
+fn exported_config_round_trips_to_the_same_tlv() {
+	// Power cycle on reset off, so the inverted DISABLE_POWER_RESET bit is set
+	let phy = [
+		0x00, 0x04, 0x12, 0x09, 0x48, 0x23, // VID:PID
+		0x04, 0x01, 0x19, // LED GPIO 25
+		0x05, 0x01, 0x08, // LED brightness
+		0x06, 0x02, 0x00, 0x06, // Opts: LED_DIMMABLE | DISABLE_POWER_RESET
+		0x08, 0x01, 0x0F, // Presence timeout
+		0x09, 0x06, b'F', b'o', b'r', b'g', b'e', 0x00, // Product
+		0x0A, 0x04, 0x00, 0x00, 0x01, 0x81, // Curves: secp256r1, ed25519, ed448
+		0x0C, 0x01, 0x02, // LED driver
+	];
+	let card = MockCard::new().with_phy(&phy);
+
+	let exported = serde_json::to_value(read_phy_config(&card).unwrap()).unwrap();
+	let serde_json::Value::Object(fields) = exported else {
+		panic!("AppConfig doesn't serialize to an object");
+	};
+	let (input, _) = parse_config_fields(fields, "export", None).unwrap();
+	assert_eq!(input.power_cycle_on_reset, Some(false));
+
+	let tlv = build_config_tlv(&input, &phy).unwrap();
+	assert_eq!(merge_phy_tlv(&phy, &tlv).unwrap(), phy);
+	assert!(drop_unchanged_tags(&tlv, &phy).unwrap().is_empty());
+}
+
+#[test]
+fn transmit_read_reassembles_get_response_chain() {
+	let apdu = [PHY_READ, &[0x00]].concat();
+	let get_response = [APDU_CLA_ISO, APDU_INS_GET_RESPONSE, 0x00, 0x00];
+	let card = MockCard::new()
+		.respond(PHY_READ, vec![0x05, 0x01, SW1_MORE_DATA, 0x03])
+		.respond(&get_response, ok(&[0x08, 0x08, 0x01]));
+
+	let data = transmit_read(&card, &apdu, "read").unwrap();
+
+	assert_eq!(data, [0x05, 0x01, 0x08, 0x08, 0x01]);
+	assert_eq!(
+		card.sent(),
+		vec![apdu, [&get_response[..], &[0x03]].concat()]
+	);
+}
+
+#[test]
+fn app_config_serializes_camel_case_keys() {
+	let config = AppConfig {
+		led_driver: Some(LedDriver::Pimoroni),
+		..Default::default()
+	};
+
+	let serde_json::Value::Object(json) = serde_json::to_value(&config).unwrap() else {
+		panic!("AppConfig doesn't serialize to an object");
+	};
+	for key in [
+		"vid",
+		"productName",
+		"ledGpio",
+		"touchTimeout",
+		"powerCycleOnReset",
+	] {
+		assert!(json.contains_key(key), "{} missing", key);
+	}
+	for key in json.keys() {
+		assert!(!key.contains('_'), "{} isn't camelCase", key);
+	}
+}
+
+#[test]
+fn app_config_input_accepts_ui_payload() {
+	// As sent by the device manager in the frontend
+	let payload = serde_json::json!({
+		"vid": "CAFE",
+		"pid": "4242",
+		"productName": "Pico Key",
+		"ledGpio": 25,
+		"ledBrightness": 8,
+		"touchTimeout": 15,
+		"ledDimmable": true,
+		"powerCycleOnReset": false,
+		"ledSteady": true,
+		"enableSecp256k1": true,
+		"ledDriver": 1,
+	});
+
+	let input: AppConfigInput = serde_json::from_value(payload).unwrap();
+
+	assert_eq!(input.product_name.as_deref(), Some("Pico Key"));
+	assert_eq!(input.led_gpio, Some(25));
+	assert_eq!(input.touch_timeout, Some(15));
+	assert_eq!(input.power_cycle_on_reset, Some(false));
+	assert_eq!(input.enable_secp256k1, Some(true));
+	assert!(input.led_driver.is_some());
+}
+
+#[test]
+fn parse_usb_id_accepts_prefix_and_either_case() {
+	assert_eq!(parse_usb_id("cafe", "vid").unwrap(), 0xCAFE);
+	assert_eq!(parse_usb_id("0x1209", "vid").unwrap(), 0x1209);
+	assert_eq!(parse_usb_id(" 0XaBcD ", "pid").unwrap(), 0xABCD);
+}
+
+#[test]
+fn parse_usb_id_rejects_wrong_length() {
+	for input in ["1", "12345", "0x", "0x123", ""] {
+		let err = parse_usb_id(input, "vid").unwrap_err();
+		assert!(
+			matches!(&err, PFError::InvalidHex { field, reason }
+				if field == "vid" && reason.starts_with("must be 4 hex digits")),
+			"{}: {}",
+			input,
+			err
+		);
+	}
+}
+
+#[test]
+fn parse_usb_id_rejects_non_hex() {
+	for input in ["12G4", "0x12g4", "+123", "12 4"] {
+		let err = parse_usb_id(input, "pid").unwrap_err();
+		assert!(
+			matches!(&err, PFError::InvalidHex { field, reason }
+				if field == "pid" && reason.starts_with("must only contain hex digits")),
+			"{}: {}",
+			input,
+			err
+		);
+	}
+}
+
+#[test]
+fn build_config_tlv_changes_only_led_steady_bit() {
+	// LED_DIMMABLE | DISABLE_POWER_RESET and a bit this tool has no field for
+	let current = [0x06, 0x02, 0x80, 0x06];
+	let input = AppConfigInput {
+		led_steady: Some(true),
+		..Default::default()
+	};
+
+	assert_eq!(
+		build_config_tlv(&input, &current).unwrap(),
+		[0x06, 0x02, 0x80, 0x0E]
+	);
+
+	let input = AppConfigInput {
+		led_steady: Some(false),
+		..Default::default()
+	};
+	assert_eq!(
+		build_config_tlv(&input, &[0x06, 0x02, 0x80, 0x0E]).unwrap(),
+		current
+	);
+}
+
+/// Select response of a device with the given MCU byte, without serial
+fn select_for_mcu(mcu: u8) -> Vec<u8> {
+	ok(&[mcu, 0x02, 0x07, 0x00])
+}
+
+#[test]
+fn led_gpio_range_depends_on_mcu() {
+	let input = |gpio| AppConfigInput {
+		led_gpio: Some(gpio),
+		..Default::default()
+	};
+
+	let rp2040 = select_for_mcu(PicoMcu::Rp2040 as u8);
+	let tlv = build_config_tlv(&input(29), &[]).unwrap();
+	assert!(check_led_gpio(&tlv, &rp2040).is_ok());
+
+	let tlv = build_config_tlv(&input(30), &[]).unwrap();
+	assert!(matches!(
+		check_led_gpio(&tlv, &rp2040),
+		Err(PFError::ValueOutOfRange {
+			value: 30,
+			max: 29,
+			..
+		})
+	));
+	assert!(check_led_gpio(&tlv, &select_for_mcu(PicoMcu::Rp2350 as u8)).is_ok());
+	assert!(check_led_gpio(&tlv, &select_for_mcu(PicoMcu::Esp32 as u8)).is_ok());
+
+	// No MCU has GPIO 49, refused before connecting
+	assert!(matches!(
+		build_config_tlv(&input(49), &[]),
+		Err(PFError::ValueOutOfRange { value: 49, .. })
+	));
+}
+
+#[test]
+fn led_brightness_rejected_above_max() {
+	let input = |brightness| AppConfigInput {
+		led_brightness: Some(brightness),
+		..Default::default()
+	};
+
+	assert_eq!(
+		build_config_tlv(&input(15), &[]).unwrap(),
+		[PhyTag::LedBrightness as u8, 0x01, 0x0F]
+	);
+	assert!(matches!(
+		build_config_tlv(&input(16), &[]),
+		Err(PFError::ValueOutOfRange {
+			value: 16,
+			max: 15,
+			..
+		})
+	));
+}
+
+#[test]
+fn unknown_led_driver_lists_the_accepted_ids() {
+	let input = AppConfigInput {
+		led_driver: Some(LedDriver::Unknown(9)),
+		..Default::default()
+	};
+
+	let err = build_config_tlv(&input, &[]).unwrap_err();
+	assert!(matches!(
+		&err,
+		PFError::NotAccepted { value: 9, accepted, .. } if accepted == &[1, 2, 3, 4, 5]
+	));
+	assert_eq!(
+		err.to_string(),
+		"ledDriver 9 not accepted (allowed 1, 2, 3, 4, 5)"
+	);
+}
+
+#[test]
+#[ignore = "needs a PC/SC service, and a device for the reads to reach the card"]
+fn commands_and_status_polling_do_not_clash() {
+	use std::sync::Arc;
+
+	// Polls like the hotplug watcher, on its own context
+	let watcher_ctx = Context::establish(Scope::User).unwrap();
+	let stop = Arc::new(AtomicBool::new(false));
+	let watcher = std::thread::spawn({
+		let ctx = watcher_ctx.clone();
+		let stop = stop.clone();
+		move || {
+			let mut polls = 0;
+			while !stop.load(Ordering::Relaxed) {
+				let mut states = vec![ReaderState::new(PNP_NOTIFICATION(), State::UNAWARE)];
+				for name in list_reader_cstrings(&ctx).unwrap_or_default() {
+					states.push(ReaderState::new(name, State::UNAWARE));
+				}
+				match ctx.get_status_change(Some(Duration::from_millis(50)), &mut states) {
+					Ok(()) | Err(pcsc::Error::Timeout) => polls += 1,
+					Err(pcsc::Error::Cancelled) => break,
+					Err(e) => panic!("watcher poll failed: {}", e),
+				}
+			}
+			polls
+		}
+	});
+
+	let (done, finished) = std::sync::mpsc::channel();
+	std::thread::spawn(move || {
+		let state = PcscState::default();
+		for _ in 0..200 {
+			let _guard = state.lock_device();
+			let result = connect_and_select(&state, None).and_then(|(card, _)| read_phy_raw(&card));
+			if let Err(PFError::Pcsc(pcsc::Error::SharingViolation)) = result {
+				panic!("read hit a sharing violation while the watcher was polling");
+			}
+		}
+		done.send(()).unwrap();
+	});
+
+	let reads = finished.recv_timeout(Duration::from_secs(60));
+	stop.store(true, Ordering::Relaxed);
+	watcher_ctx.cancel().unwrap();
+	let polls = watcher.join().unwrap();
+
+	reads.expect("reads deadlocked or panicked while the watcher was polling");
+	assert!(polls > 0);
+}
+
+/// PHY config with VID:PID CAFE:4242, brightness 8, dimmable LED and product "Pico Key"
+const SAMPLE_PHY: &[u8] = &[
+	0x00, 0x04, 0xCA, 0xFE, 0x42, 0x42, // VID:PID
+	0x05, 0x01, 0x08, // LED brightness
+	0x06, 0x02, 0x00, 0x02, // Opts: LED_DIMMABLE
+	0x09, 0x09, b'P', b'i', b'c', b'o', b' ', b'K', b'e', b'y', 0x00, // Product
+	0x0A, 0x04, 0x00, 0x00, 0x00, 0x89, // Curves: secp256r1, secp256k1, ed25519
+];
+
+#[test]
+fn parse_phy_tlv_reads_config_from_card() {
+	let card = MockCard::new().with_phy(SAMPLE_PHY);
+
+	let config = read_phy_config(&card).unwrap();
+
+	assert_eq!(card.sent(), vec![[PHY_READ, &[0x00]].concat()]);
+	assert_eq!(config.vid, "CAFE");
+	assert_eq!(config.pid, "4242");
+	assert_eq!(config.led_brightness, 8);
+	assert_eq!(config.product_name, "Pico Key");
+	assert!(config.led_dimmable);
+	assert!(!config.led_steady);
+	assert!(config.power_cycle_on_reset);
+	assert!(config.enable_secp256r1 && config.enable_secp256k1 && config.enable_ed25519);
+	assert!(!config.enable_ed448);
+}
+
+#[test]
+fn parse_phy_tlv_warns_on_short_known_tag() {
+	let (config, warnings) = parse_phy_tlv(&[0x00, 0x02, 0xCA, 0xFE, 0x05, 0x01, 0x07]);
+
+	assert_eq!(warnings.len(), 1);
+	assert_eq!(warnings[0].tag, PhyTag::VidPid as u8);
+	assert_eq!(config.led_brightness, 7);
+}
+
+#[test]
+fn build_config_tlv_encodes_changed_fields_only() {
+	let input = AppConfigInput {
+		led_brightness: Some(5),
+		..Default::default()
+	};
+	assert_eq!(build_config_tlv(&input, &[]).unwrap(), [0x05, 0x01, 0x05]);
+
+	assert!(
+		build_config_tlv(&AppConfigInput::default(), SAMPLE_PHY)
+			.unwrap()
+			.is_empty()
+	);
+}
+
+#[test]
+fn build_config_tlv_round_trips_through_parse() {
+	let input = AppConfigInput {
+		vid: Some("1209".into()),
+		pid: Some("4823".into()),
+		product_name: Some("Forge".into()),
+		led_brightness: Some(3),
+		led_steady: Some(true),
+		enable_ed448: Some(true),
+		..Default::default()
+	};
+
+	let tlv = build_config_tlv(&input, SAMPLE_PHY).unwrap();
+	let (config, warnings) = parse_phy_tlv(&merge_phy_tlv(SAMPLE_PHY, &tlv).unwrap());
+
+	assert!(warnings.is_empty());
+	assert_eq!((config.vid.as_str(), config.pid.as_str()), ("1209", "4823"));
+	assert_eq!(config.product_name, "Forge");
+	assert_eq!(config.led_brightness, 3);
+	assert!(config.led_steady);
+	// Untouched opts and curves bits come from the device value
+	assert!(config.led_dimmable);
+	assert!(config.enable_ed448 && config.enable_secp256r1 && config.enable_ed25519);
+}
+
+/// `SAMPLE_PHY` with its brightness set to `brightness`
+fn sample_phy_with_brightness(brightness: u8) -> Vec<u8> {
+	let mut tlv = Vec::new();
+	push_tlv_u8(&mut tlv, PhyTag::LedBrightness, brightness).unwrap();
+	merge_phy_tlv(SAMPLE_PHY, &tlv).unwrap()
+}
+
+#[test]
+fn blink_led_toggles_and_restores_the_brightness() {
+	let card = MockCard::new().with_phy(SAMPLE_PHY);
+
+	assert_eq!(blink_led(&card, 2, Duration::ZERO).unwrap(), 0x08);
+
+	let on = sample_phy_with_brightness(LED_BRIGHTNESS_MAX);
+	let off = sample_phy_with_brightness(0);
+	assert_eq!(card.phy_writes(), [&on, &off, &on, &off, SAMPLE_PHY]);
+}
+
+#[test]
+fn blink_led_restores_the_brightness_after_a_failed_write() {
+	// PHY writes are refused with 6D00, the guard still tries to put 08 back
+	let card = MockCard::new().respond(PHY_READ, ok(SAMPLE_PHY));
+
+	assert!(blink_led(&card, 2, Duration::ZERO).is_err());
+	assert_eq!(
+		card.phy_writes(),
+		[
+			sample_phy_with_brightness(LED_BRIGHTNESS_MAX),
+			SAMPLE_PHY.to_vec()
+		]
+	);
+}
+
+#[test]
+fn blink_led_refuses_without_led_dimmable() {
+	let mut tlv = Vec::new();
+	push_tlv(&mut tlv, PhyTag::Opts, &[0x00, 0x00]).unwrap();
+	let card = MockCard::new().with_phy(&merge_phy_tlv(SAMPLE_PHY, &tlv).unwrap());
+
+	assert!(blink_led(&card, 2, Duration::ZERO).is_err());
+	assert!(card.phy_writes().is_empty());
+}
+
+#[test]
+fn merge_phy_tlv_replaces_and_appends_in_the_write() {
+	let card = MockCard::new().with_phy(&[0x05, 0x01, 0x08, 0x08, 0x01, 0x0F]);
+	let changes = [0x05, 0x01, 0x02, 0x0C, 0x01, 0x01];
+
+	write_phy_tlv(&card, &changes).unwrap();
+
+	// Brightness replaced in place, touch timeout kept, LED driver appended
+	assert_eq!(
+		card.phy_writes(),
+		vec![vec![0x05, 0x01, 0x02, 0x08, 0x01, 0x0F, 0x0C, 0x01, 0x01]]
+	);
+}
+
+#[test]
+fn list_with_growing_buffer_retries_until_the_list_fits() {
+	let mut tried = Vec::new();
+	// A reader shows up after the first size query, so the first retry is still too small
+	let mut needed = [READERS_BUF_LEN + 1, 3 * READERS_BUF_LEN].into_iter();
+
+	let len = list_with_growing_buffer(
+		|buf| {
+			tried.push(buf.len());
+			if buf.len() < 3 * READERS_BUF_LEN {
+				Err(pcsc::Error::InsufficientBuffer)
+			} else {
+				Ok(buf.len())
+			}
+		},
+		|| Ok(needed.next().unwrap()),
+	)
+	.unwrap();
+
+	assert_eq!(len, 4 * READERS_BUF_LEN);
+	assert_eq!(
+		tried,
+		[READERS_BUF_LEN, 2 * READERS_BUF_LEN, 4 * READERS_BUF_LEN]
+	);
+}
+
+#[test]
+fn list_with_growing_buffer_passes_other_errors_through() {
+	let result: Result<(), _> = list_with_growing_buffer(
+		|_| Err(pcsc::Error::NoReadersAvailable),
+		|| panic!("size queried without InsufficientBuffer"),
+	);
+	assert_eq!(result, Err(pcsc::Error::NoReadersAvailable));
+
+	let result: Result<(), _> = list_with_growing_buffer(
+		|_| Err(pcsc::Error::InsufficientBuffer),
+		|| Err(pcsc::Error::NoService),
+	);
+	assert_eq!(result, Err(pcsc::Error::NoService));
+}
+
+#[test]
+fn atr_historical_bytes_without_interface_bytes() {
+	assert_eq!(
+		atr_historical_bytes(&[0x3B, 0x02, 0x14, 0x50]),
+		Some(&[0x14, 0x50][..])
+	);
+	assert_eq!(atr_historical_bytes(&[0x3B, 0x00]), Some(&[][..]));
+}
+
+#[test]
+fn atr_historical_bytes_skips_interface_bytes() {
+	// TD1 and TD2 (T=1), then 4 historical bytes and TCK
+	let atr = [0x3B, 0x84, 0x80, 0x01, b'P', b'i', b'c', b'o', 0x12];
+	assert_eq!(atr_historical_bytes(&atr), Some(&b"Pico"[..]));
+
+	// TA1 and TD1, then TD2 announcing TA3 and TB3 (T=1), then 2 historical bytes and TCK
+	let atr = [0x3B, 0x92, 0x13, 0x81, 0x31, 0xFE, 0x45, 0x80, 0x31, 0x7A];
+	assert_eq!(atr_historical_bytes(&atr), Some(&[0x80, 0x31][..]));
+}
+
+#[test]
+fn atr_historical_bytes_rejects_short_atr() {
+	assert_eq!(atr_historical_bytes(&[]), None);
+	assert_eq!(atr_historical_bytes(&[0x3B]), None);
+	// TD1 announces TD2, which is missing
+	assert_eq!(atr_historical_bytes(&[0x3B, 0x80, 0x80]), None);
+	// 5 historical bytes announced, 2 present
+	assert_eq!(atr_historical_bytes(&[0x3B, 0x05, 0x01, 0x02]), None);
+}
+
+#[test]
+fn power_reset_is_the_inverse_of_disable_power_reset() {
+	let mut opts = RescueOptions::LED_DIMMABLE;
+	assert!(opts.power_reset());
+
+	opts.set_power_reset(false);
+	assert_eq!(
+		opts,
+		RescueOptions::LED_DIMMABLE | RescueOptions::DISABLE_POWER_RESET
+	);
+	assert!(!opts.power_reset());
+
+	opts.set_power_reset(true);
+	assert_eq!(opts, RescueOptions::LED_DIMMABLE);
+	assert!(opts.power_reset());
+}
+
+#[test]
+fn power_cycle_on_reset_writes_the_inverted_bit() {
+	let write = |enabled, current: &[u8]| {
+		let input = AppConfigInput {
+			power_cycle_on_reset: Some(enabled),
+			..Default::default()
+		};
+		build_config_tlv(&input, current).unwrap()
+	};
+
+	assert_eq!(
+		write(false, &[0x06, 0x02, 0x00, 0x02]),
+		[0x06, 0x02, 0x00, 0x06]
+	);
+	assert_eq!(
+		write(true, &[0x06, 0x02, 0x00, 0x06]),
+		[0x06, 0x02, 0x00, 0x02]
+	);
+
+	let (config, _) = parse_phy_tlv(&[0x06, 0x02, 0x00, 0x04]);
+	assert!(!config.power_cycle_on_reset);
+	let (config, _) = parse_phy_tlv(&[0x06, 0x02, 0x00, 0x00]);
+	assert!(config.power_cycle_on_reset);
+}
+
+#[test]
+fn format_serial_groups_by_four() {
+	assert_eq!(format_serial("0123456789ABCDEF"), "0123-4567-89AB-CDEF");
+	assert_eq!(format_serial("ABCD"), "ABCD");
+	assert_eq!(format_serial(""), "");
+}
+
+#[test]
+fn format_serial_keeps_a_short_trailing_group() {
+	assert_eq!(format_serial("0123456789"), "0123-4567-89");
+	assert_eq!(format_serial("ABC"), "ABC");
+}
+
+#[test]
+fn drop_unchanged_tags_empties_an_unchanged_write() {
+	let input = AppConfigInput {
+		led_brightness: Some(8),
+		product_name: Some("Pico Key".into()),
+		..Default::default()
+	};
+	let tlv = build_config_tlv(&input, SAMPLE_PHY).unwrap();
+	assert!(!tlv.is_empty());
+
+	assert!(drop_unchanged_tags(&tlv, SAMPLE_PHY).unwrap().is_empty());
+}
+
+#[test]
+fn drop_unchanged_tags_keeps_changed_fields() {
+	let input = AppConfigInput {
+		led_brightness: Some(8),
+		led_steady: Some(true),
+		..Default::default()
+	};
+	let tlv = build_config_tlv(&input, SAMPLE_PHY).unwrap();
+
+	assert_eq!(
+		drop_unchanged_tags(&tlv, SAMPLE_PHY).unwrap(),
+		[0x06, 0x02, 0x00, 0x0A]
+	);
+}
+
+#[test]
+fn toml_and_json_profiles_produce_the_same_tlv() {
+	let toml = std::fs::read_to_string(fixture("profile.toml")).unwrap();
+	let toml_fields = toml::from_str(&toml).unwrap();
+	let json_fields = read_config_file(&fixture("profile.json")).unwrap();
+
+	let (from_toml, toml_version) = parse_config_fields(toml_fields, "TOML", None).unwrap();
+	let (from_json, json_version) = parse_config_fields(json_fields, "JSON", None).unwrap();
+
+	assert_eq!(toml_version, json_version);
+	let toml_tlv = build_config_tlv(&from_toml, SAMPLE_PHY).unwrap();
+	assert!(!toml_tlv.is_empty());
+	assert_eq!(toml_tlv, build_config_tlv(&from_json, SAMPLE_PHY).unwrap());
+}
+
+#[test]
+fn merge_phy_tlv_keeps_unknown_tags() {
+	let card = MockCard::new().with_phy(&[0x7E, 0x03, 0xAA, 0xBB, 0xCC, 0x05, 0x01, 0x08]);
+
+	write_phy_tlv(&card, &[0x05, 0x01, 0x02]).unwrap();
+
+	assert_eq!(
+		card.phy_writes(),
+		vec![vec![0x7E, 0x03, 0xAA, 0xBB, 0xCC, 0x05, 0x01, 0x02]]
+	);
+}
+
+#[test]
+fn merge_phy_tlv_copies_truncated_tail_through() {
+	// The last entry claims 4 bytes but only 1 is left
+	let current = [0x05, 0x01, 0x08, 0x7E, 0x04, 0xAA];
+
+	let merged = merge_phy_tlv(&current, &[0x05, 0x01, 0x02, 0x0C, 0x01, 0x01]).unwrap();
+
+	assert_eq!(
+		merged,
+		[0x05, 0x01, 0x02, 0x0C, 0x01, 0x01, 0x7E, 0x04, 0xAA]
+	);
+}
+
+#[test]
+fn tlv_length_reads_short_and_long_forms() {
+	for form in [
+		&[0x05, 0x01, 0x09][..],
+		&[0x05, 0x81, 0x01, 0x09],
+		&[0x05, 0x82, 0x00, 0x01, 0x09],
+	] {
+		let (config, warnings) = parse_phy_tlv(&[form, &[0x08, 0x01, 0x0F]].concat());
+
+		assert!(warnings.is_empty(), "{:02X?}", form);
+		assert_eq!(config.led_brightness, 9, "{:02X?}", form);
+		assert_eq!(config.touch_timeout, 15, "{:02X?}", form);
+	}
+}
+
+#[test]
+fn push_tlv_length_round_trips_through_tlv_length() {
+	for len in [0, 1, 0x7E, 0x7F] {
+		let mut buf = Vec::new();
+		push_tlv_length(&mut buf, PhyTag::UsbProduct as u8, len).unwrap();
+
+		assert_eq!(buf, [len as u8]);
+		assert_eq!(tlv_length(&buf), Some((len, 1)));
+	}
+
+	// 81 and 82 would read back as long forms, so every length past 7F is refused
+	for len in [0x80, 0x81, 0x82, 0xFF, 0x100] {
+		let mut buf = Vec::new();
+		assert!(push_tlv_length(&mut buf, PhyTag::UsbProduct as u8, len).is_err());
+		assert!(buf.is_empty());
+	}
+}
+
+#[test]
+fn parse_phy_tlv_warns_on_long_length_past_the_end() {
+	// 81 04 claims four bytes, only three follow
+	let (config, warnings) = parse_phy_tlv(&[0x05, 0x01, 0x07, 0x00, 0x81, 0x04, 0xCA, 0xFE, 0x42]);
+
+	assert_eq!(config.led_brightness, 7);
+	assert_eq!(warnings.len(), 1);
+	assert_eq!(warnings[0].tag, PhyTag::VidPid as u8);
+
+	// Cut off inside the long form length itself
+	let (_, warnings) = parse_phy_tlv(&[0x00, 0x82, 0x00]);
+	assert_eq!(warnings.len(), 1);
+}
+
+fn fixture(name: &str) -> std::path::PathBuf {
+	std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+		.join("tests/fixtures")
+		.join(name)
+}
+
+#[test]
+fn parse_config_fields_reads_profile_fixture() {
+	let fields = read_config_file(&fixture("profile.json")).unwrap();
+
+	let (config, schema_version) = parse_config_fields(fields, "profile.json", None).unwrap();
+
+	assert_eq!(schema_version, Some(1));
+	assert_eq!(config.vid.as_deref(), Some("1209"));
+	assert_eq!(config.pid.as_deref(), Some("4823"));
+	assert_eq!(config.product_name.as_deref(), Some("Forge Key"));
+	assert_eq!(config.led_brightness, Some(4));
+	assert_eq!(config.touch_timeout, Some(15));
+	assert_eq!(config.led_steady, Some(true));
+	assert_eq!(config.led_dimmable, None);
+}
+
+#[test]
+fn parse_config_fields_applies_only_filter() {
+	let mut fields = read_config_file(&fixture("profile.json")).unwrap();
+	fields.insert("allowTouchDisable".into(), true.into());
+	let only = ["touchTimeout".to_owned()];
+
+	let (config, _) = parse_config_fields(fields, "profile.json", Some(&only)).unwrap();
+
+	assert_eq!(config.touch_timeout, Some(15));
+	assert_eq!(config.allow_touch_disable, Some(true));
+	assert_eq!(config.vid, None);
+	assert_eq!(config.led_brightness, None);
+
+	let fields = read_config_file(&fixture("profile.json")).unwrap();
+	let only = ["brightness".to_owned()];
+	let err = parse_config_fields(fields, "profile.json", Some(&only)).unwrap_err();
+	assert!(matches!(err, PFError::Io(msg) if msg.contains("only filter: brightness")));
+}
+
+#[test]
+fn parse_config_fields_rejects_unknown_fields() {
+	let mut fields = read_config_file(&fixture("profile.json")).unwrap();
+	fields.insert("ledColour".into(), "red".into());
+	let only = ["vid".to_owned()];
+
+	// Also with a filter that wouldn't select the unknown field
+	for only in [None, Some(&only[..])] {
+		let err = parse_config_fields(fields.clone(), "profile.json", only).unwrap_err();
+		assert!(
+			matches!(&err, PFError::Io(msg) if msg.contains("unknown field `ledColour`")),
+			"{}",
+			err
+		);
+	}
+}
+
+#[test]
+fn read_config_file_reports_missing_file_and_invalid_json() {
+	let err = read_config_file(&fixture("missing.json")).unwrap_err();
+	assert!(matches!(err, PFError::Io(msg) if msg.starts_with("Config file not found")));
+
+	let path = std::env::temp_dir().join(format!("picoforge-invalid-{}.json", std::process::id()));
+	std::fs::write(&path, "{ \"vid\": ").unwrap();
+	let err = read_config_file(&path).unwrap_err();
+	std::fs::remove_file(&path).unwrap();
+	assert!(matches!(err, PFError::Io(msg) if msg.starts_with("Invalid JSON")));
+}
+
+#[test]
+fn exported_fields_are_importable_or_export_only() {
+	let config = AppConfig {
+		led_driver: Some(LedDriver::Ws2812),
+		ccid_enabled: Some(true),
+		..Default::default()
+	};
+
+	let serde_json::Value::Object(exported) = serde_json::to_value(&config).unwrap() else {
+		panic!("AppConfig doesn't serialize to an object");
+	};
+	for key in exported.keys() {
+		assert!(
+			is_input_field(key) != EXPORT_ONLY_FIELDS.contains(&key.as_str()),
+			"{} must be either an AppConfigInput field or in EXPORT_ONLY_FIELDS",
+			key
+		);
+	}
+}
@@ -0,0 +1,126 @@
+//! Abstraction over the APDU exchange with a card, so the protocol helpers in this module
+//! don't depend on a live PCSC connection.
+
+use crate::error::PFError;
+use pcsc::{Disposition, Protocols, ShareMode};
+use std::ops::Deref;
+
+/// A single command/response exchange with the applet.
+///
+/// Implementations send exactly one APDU and return the raw response including the
+/// status word, `61XX` chaining and status checks are handled by the callers.
+pub trait ApduTransport {
+	fn transmit(&self, apdu: &[u8]) -> Result<Vec<u8>, PFError>;
+
+	/// Answer To Reset of the card, if the transport knows it
+	fn atr(&self) -> Option<Vec<u8>> {
+		None
+	}
+}
+
+/// Receive buffer size for a single exchange, room for an extended length response so
+/// firmware returning more than a short APDU's 256 bytes is never truncated.
+pub const RX_BUFFER_LEN: usize = pcsc::MAX_BUFFER_SIZE_EXTENDED;
+
+/// Sends one APDU on `card`, all PCSC transmits go through here.
+/// The response is returned as an owned buffer holding exactly the received bytes.
+pub fn transmit_card(card: &pcsc::Card, apdu: &[u8]) -> Result<Vec<u8>, PFError> {
+	let mut rx_buf = vec![0; RX_BUFFER_LEN];
+	// Inherent `Card::transmit`, not the trait method
+	let len = pcsc::Card::transmit(card, apdu, &mut rx_buf)?.len();
+	rx_buf.truncate(len);
+	Ok(rx_buf)
+}
+
+impl ApduTransport for pcsc::Card {
+	fn transmit(&self, apdu: &[u8]) -> Result<Vec<u8>, PFError> {
+		transmit_card(self, apdu)
+	}
+
+	fn atr(&self) -> Option<Vec<u8>> {
+		match self.status2_owned() {
+			Ok(status) => Some(status.atr().to_vec()),
+			Err(e) => {
+				log::warn!("Failed to read card ATR: {}", e);
+				None
+			}
+		}
+	}
+}
+
+/// A connected card that ends its connection with a chosen `Disposition` when dropped.
+///
+/// A plain `pcsc::Card` always resets the card on drop. This defaults to `LeaveCard`, so
+/// reads don't disturb the card, while writes that make the firmware re-apply its config
+/// ask for `ResetCard` so the next command starts from a fresh power-up state.
+pub struct ConnectedCard {
+	card: Option<pcsc::Card>,
+	disposition: Disposition,
+}
+
+impl ConnectedCard {
+	pub fn new(card: pcsc::Card) -> Self {
+		Self {
+			card: Some(card),
+			disposition: Disposition::LeaveCard,
+		}
+	}
+
+	/// Sets what happens to the card when the connection ends
+	pub fn set_disposition(&mut self, disposition: Disposition) {
+		self.disposition = disposition;
+	}
+
+	/// Re-establishes the connection on the same handle after the card was reset.
+	///
+	/// The handle is never released, so an exclusive connection stays exclusive and no
+	/// other application can reach the card in between.
+	pub fn reconnect(&mut self, mode: ShareMode) -> Result<(), PFError> {
+		self.card
+			.as_mut()
+			.expect("card already disconnected")
+			.reconnect(mode, Protocols::ANY, Disposition::LeaveCard)?;
+		Ok(())
+	}
+
+	/// Ends the connection now, reporting a failed disconnect instead of only logging it
+	pub fn disconnect(mut self) -> Result<(), PFError> {
+		match self.card.take() {
+			Some(card) => card
+				.disconnect(self.disposition)
+				.map_err(|(_, e)| PFError::Pcsc(e)),
+			None => Ok(()),
+		}
+	}
+}
+
+impl Deref for ConnectedCard {
+	type Target = pcsc::Card;
+
+	fn deref(&self) -> &pcsc::Card {
+		// Only taken by `disconnect` and `drop`, which both consume the wrapper
+		self.card.as_ref().expect("card already disconnected")
+	}
+}
+
+impl Drop for ConnectedCard {
+	fn drop(&mut self) {
+		let Some(card) = self.card.take() else {
+			return;
+		};
+		// A card that went away (e.g. rebooted) can't be disconnected cleanly, nothing to do
+		if let Err((_, e)) = card.disconnect(self.disposition) {
+			log::debug!("Card disconnect ({:?}) failed: {}", self.disposition, e);
+		}
+	}
+}
+
+impl ApduTransport for ConnectedCard {
+	fn transmit(&self, apdu: &[u8]) -> Result<Vec<u8>, PFError> {
+		transmit_card(self, apdu)
+	}
+
+	fn atr(&self) -> Option<Vec<u8>> {
+		ApduTransport::atr(&**self)
+	}
+}
@@ -1,18 +1,94 @@
 #![allow(unused)]
 
+use crate::rescue::constants::{
+	LOW_FLASH_FREE_PERCENT, LedDriver, SecureBootAlgorithm, TOUCH_TIMEOUT_DISABLED,
+};
 use serde::{Deserialize, Serialize};
 
 struct PForgeState {
 	device_info: DeviceInfo,
 }
 
+/// Firmware version from the select response, ordered by `major` then `minor`
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub struct FirmwareVersion {
+	pub major: u8,
+	pub minor: u8,
+}
+
+impl FirmwareVersion {
+	pub const fn new(major: u8, minor: u8) -> Self {
+		Self { major, minor }
+	}
+}
+
+impl std::fmt::Display for FirmwareVersion {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}.{}", self.major, self.minor)
+	}
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DeviceInfo {
+	/// Uppercase hex, use this one for lookups
 	pub serial: String,
+	/// `serial` grouped for display, see `format_serial`
+	pub serial_label: String,
 	pub flash_used: u32,
 	pub flash_total: u32,
+	/// Free share of `flash_total`, 0 when the total is unknown
+	pub flash_free_percent: u8,
+	/// Less than `LOW_FLASH_FREE_PERCENT` free, never set when the total is unknown
+	pub low_space: bool,
+	/// Number of files (resident credentials, keys...) stored in flash
+	pub file_count: u32,
+	/// Display form of `firmware`, kept for existing consumers
 	pub firmware_version: String,
+	/// `None` when the version isn't known in a comparable form
+	pub firmware: Option<FirmwareVersion>,
+	/// Raw MCU byte from the select response
+	pub mcu: u8,
+	pub mcu_name: String,
+	/// Raw product byte from the select response
+	pub product: u8,
+	pub product_label: String,
+	/// Power-on / usage counter, for lifecycle tracking.
+	///
+	/// NOTE: No current firmware reports one, neither the flash info (`free, used, total,
+	/// files, chip size`) nor the secure boot read carry a counter, so this is always
+	/// `None` until a firmware adds it.
+	pub boot_count: Option<u32>,
+	/// ISO 8601 manufacture date.
+	///
+	/// NOTE: No current firmware reports one, the select response ends after the serial
+	/// and no documented tag carries a date, so this is always `None`.
+	pub manufacture_date: Option<String>,
+}
+
+/// Computes `(flash_free_percent, low_space)` for a `DeviceInfo` from used and total flash
+pub fn flash_free_stats(used: u32, total: u32) -> (u8, bool) {
+	if total == 0 {
+		return (0, false);
+	}
+	let free = total.saturating_sub(used) as u64;
+	let percent = (free * 100 / total as u64) as u8;
+	(percent, percent < LOW_FLASH_FREE_PERCENT)
+}
+
+/// Hex digits per dash separated group in `DeviceInfo::serial_label`
+pub const SERIAL_GROUP_LEN: usize = 4;
+
+/// Groups a hex serial for display, e.g. `0123456789ABCDEF` -> `0123-4567-89AB-CDEF`.
+/// A trailing group may be shorter when the length isn't a multiple of the group size.
+pub fn format_serial(serial: &str) -> String {
+	serial
+		.as_bytes()
+		.chunks(SERIAL_GROUP_LEN)
+		.map(String::from_utf8_lossy)
+		.collect::<Vec<_>>()
+		.join("-")
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -23,29 +99,118 @@ pub struct AppConfig {
 	pub product_name: String,
 	pub led_gpio: u8,
 	pub led_brightness: u8,
+	/// Presence (touch) timeout in seconds, 0 means presence checking is disabled
 	pub touch_timeout: u8,
 	#[serde(skip_serializing_if = "Option::is_none")]
-	pub led_driver: Option<u8>,
+	pub led_driver: Option<LedDriver>,
 	pub led_dimmable: bool,
 	pub power_cycle_on_reset: bool,
 	pub led_steady: bool,
 	pub enable_secp256k1: bool,
+	pub enable_secp256r1: bool,
+	pub enable_ed25519: bool,
+	pub enable_ed448: bool,
+	/// Complete `TAG_OPTS` value, including bits without a dedicated field
+	pub opts_raw: u16,
+	/// Complete `TAG_CURVES` mask, including curves without a dedicated field
+	pub curves_raw: u32,
+	/// Smart card interface from `TAG_ENABLED_USB_ITF`, `None` when read without the
+	/// Rescue Applet. Only written through `set_ccid_enabled`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ccid_enabled: Option<bool>,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct AppConfigInput {
 	pub vid: Option<String>,
 	pub pid: Option<String>,
 	pub product_name: Option<String>,
 	pub led_gpio: Option<u8>,
+	/// Board label such as "GP25" or "LED", only used when `led_gpio` is not set
+	pub led_pin_label: Option<String>,
 	pub led_brightness: Option<u8>,
+	/// Seconds, see `AppConfig::touch_timeout`
 	pub touch_timeout: Option<u8>,
-	pub led_driver: Option<u8>,
+	/// Must be set to write a `touch_timeout` of 0, which disables presence checking
+	pub allow_touch_disable: Option<bool>,
+	pub led_driver: Option<LedDriver>,
 	pub led_dimmable: Option<bool>,
 	pub power_cycle_on_reset: Option<bool>,
 	pub led_steady: Option<bool>,
 	pub enable_secp256k1: Option<bool>,
+	pub enable_secp256r1: Option<bool>,
+	pub enable_ed25519: Option<bool>,
+	pub enable_ed448: Option<bool>,
+}
+
+impl AppConfigInput {
+	/// Returns `self` with every field set in `newer` replaced by its value.
+	///
+	/// `led_gpio` and `led_pin_label` are taken together, so a newly staged label isn't
+	/// shadowed by an older GPIO number.
+	pub fn overlay(self, newer: Self) -> Self {
+		let (led_gpio, led_pin_label) = if newer.led_gpio.is_some() || newer.led_pin_label.is_some()
+		{
+			(newer.led_gpio, newer.led_pin_label)
+		} else {
+			(self.led_gpio, self.led_pin_label)
+		};
+
+		Self {
+			vid: newer.vid.or(self.vid),
+			pid: newer.pid.or(self.pid),
+			product_name: newer.product_name.or(self.product_name),
+			led_gpio,
+			led_pin_label,
+			led_brightness: newer.led_brightness.or(self.led_brightness),
+			touch_timeout: newer.touch_timeout.or(self.touch_timeout),
+			allow_touch_disable: newer.allow_touch_disable.or(self.allow_touch_disable),
+			led_driver: newer.led_driver.or(self.led_driver),
+			led_dimmable: newer.led_dimmable.or(self.led_dimmable),
+			power_cycle_on_reset: newer.power_cycle_on_reset.or(self.power_cycle_on_reset),
+			led_steady: newer.led_steady.or(self.led_steady),
+			enable_secp256k1: newer.enable_secp256k1.or(self.enable_secp256k1),
+			enable_secp256r1: newer.enable_secp256r1.or(self.enable_secp256r1),
+			enable_ed25519: newer.enable_ed25519.or(self.enable_ed25519),
+			enable_ed448: newer.enable_ed448.or(self.enable_ed448),
+		}
+	}
+}
+
+/// Version of the `AppConfig` layout produced by `export_config`
+pub const CONFIG_SCHEMA_VERSION: u32 = 1;
+
+impl From<AppConfig> for AppConfigInput {
+	fn from(config: AppConfig) -> Self {
+		Self {
+			vid: Some(config.vid),
+			pid: Some(config.pid),
+			product_name: Some(config.product_name),
+			led_gpio: Some(config.led_gpio),
+			led_pin_label: None,
+			led_brightness: Some(config.led_brightness),
+			touch_timeout: Some(config.touch_timeout),
+			// An exported config with presence checking off was disabled deliberately
+			allow_touch_disable: Some(config.touch_timeout == TOUCH_TIMEOUT_DISABLED),
+			led_driver: config.led_driver,
+			led_dimmable: Some(config.led_dimmable),
+			power_cycle_on_reset: Some(config.power_cycle_on_reset),
+			led_steady: Some(config.led_steady),
+			enable_secp256k1: Some(config.enable_secp256k1),
+			enable_secp256r1: Some(config.enable_secp256r1),
+			enable_ed25519: Some(config.enable_ed25519),
+			enable_ed448: Some(config.enable_ed448),
+		}
+	}
+}
+
+/// A malformed entry found while parsing the PHY config TLV
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ParseWarning {
+	pub tag: u8,
+	pub message: String,
 }
 
 #[derive(Serialize)]
@@ -53,8 +218,190 @@ pub struct AppConfigInput {
 pub struct FullDeviceStatus {
 	pub info: DeviceInfo,
 	pub config: AppConfig,
+	pub config_warnings: Vec<ParseWarning>,
 	pub secure_boot: bool,
 	pub secure_lock: bool,
+	pub secure_boot_algorithm: SecureBootAlgorithm,
+	/// Always `None` on current firmware, the secure boot read carries no version
+	pub bootloader_version: Option<FirmwareVersion>,
+	/// Secure Lock attempts left, always `None` on firmware without a retry counter
+	pub secure_lock_attempts: Option<u8>,
+	/// Names of the enabled curves, see `RescueCurves::names`
+	pub enabled_curves: Vec<String>,
+	/// Always `None`, the firmware doesn't report which curves it supports
+	pub supported_curves: Option<Vec<String>>,
+	/// The Rescue Applet wants `verify_pin` before it accepts writes
+	pub pin_required: bool,
+	/// Sections that failed to read, their fields hold defaults
+	pub warnings: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FactoryResetResult {
+	pub message: String,
+	pub config: AppConfig,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RawTlvEntry {
+	pub tag: u8,
+	pub len: u16,
+	pub value_hex: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RawPhyDump {
+	pub hex: String,
+	pub entries: Vec<RawTlvEntry>,
+	pub truncated: bool,
+}
+
+/// USB interfaces enabled in the PHY config
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsbMode {
+	/// Smart card interface, required to reach the Rescue Applet
+	pub ccid: bool,
+	pub wcid: bool,
+	/// FIDO (CTAPHID) interface
+	pub hid: bool,
+	pub keyboard: bool,
+	/// `false` if the device has no explicit setting and uses the firmware default
+	pub configured: bool,
+}
+
+/// Payload of the `write-progress` event emitted while `write_config` runs
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "stage", rename_all = "camelCase")]
+pub enum WriteProgress {
+	Started,
+	/// Fraction (0-1) of the APDUs acknowledged so far
+	Progress {
+		fraction: f32,
+	},
+	Finished {
+		success: bool,
+		message: String,
+	},
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecureBootKeySlot {
+	pub index: u8,
+	pub provisioned: bool,
+	pub algorithm: SecureBootAlgorithm,
+	/// Key hash as uppercase hex, `None` for empty slots
+	pub hash: Option<String>,
+	/// Set when reading this slot failed, distinct from an empty slot
+	pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecureBootUnlockStatus {
+	pub can_unlock: bool,
+	pub reason: String,
+}
+
+/// Outcome of a config or secure boot write
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WriteResult {
+	/// `false` when there was nothing to change and nothing was sent
+	pub applied: bool,
+	/// PHY tags that were sent, empty for writes outside the PHY config
+	pub tags_written: Vec<u8>,
+	/// The written tags were read back and matched
+	pub verified: bool,
+	/// Default human readable summary, the fields above are authoritative
+	pub message: String,
+}
+
+impl WriteResult {
+	pub fn no_changes() -> Self {
+		Self {
+			applied: false,
+			tags_written: Vec::new(),
+			verified: false,
+			message: "No changes to apply".into(),
+		}
+	}
+}
+
+/// Outcome of `provision_all` for one device
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ProvisionReport {
+	pub serial: String,
+	/// The config was written, `message` holds the error otherwise
+	pub success: bool,
+	/// The written tags were read back and matched
+	pub verified: bool,
+	pub message: String,
+}
+
+/// What `write_config` would send, as uppercase hex
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WritePreview {
+	/// Only the changed tags
+	pub changes_hex: String,
+	/// Complete PHY config after merging the changes into the device's current config
+	pub payload_hex: String,
+	/// APDUs in the order they'd be transmitted, empty if nothing changes
+	pub apdus: Vec<String>,
+}
+
+/// Outcome of a single subsystem check in a self test
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTestCheck {
+	pub name: String,
+	pub passed: bool,
+	pub detail: String,
+}
+
+impl SelfTestCheck {
+	pub fn from_result<E: std::fmt::Display>(name: &str, result: Result<String, E>) -> Self {
+		let (passed, detail) = match result {
+			Ok(detail) => (true, detail),
+			Err(e) => (false, e.to_string()),
+		};
+		Self {
+			name: name.into(),
+			passed,
+			detail,
+		}
+	}
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTestReport {
+	pub passed: bool,
+	pub checks: Vec<SelfTestCheck>,
+}
+
+/// Round trip latency of repeated flash info reads, from `benchmark_reader`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReaderBenchmark {
+	pub iterations: u32,
+	/// Iterations run before finishing or aborting
+	pub completed: u32,
+	/// Reads that failed or took longer than `BENCHMARK_ITERATION_TIMEOUT`
+	pub failures: u32,
+	/// Latency of the successful reads, all 0 when there were none
+	pub min_ms: f64,
+	pub max_ms: f64,
+	pub avg_ms: f64,
+	pub stddev_ms: f64,
+	/// Set when the card went away or a read went over budget and the run stopped early
+	pub aborted: Option<String>,
 }
 
 // Fido stuff:
@@ -83,3 +430,123 @@ pub struct StoredCredential {
 	pub user_id: String,
 	pub credential_id: String,
 }
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AtrInfo {
+	/// Complete ATR as uppercase hex
+	pub atr: String,
+	/// `None` when the ATR is malformed
+	pub historical_bytes: Option<String>,
+	/// Historical bytes after the category indicator, when they are printable ASCII
+	pub historical_text: Option<String>,
+}
+
+/// What `ping_reader` found on a reader, without selecting any applet
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReaderPing {
+	pub card_present: bool,
+	/// The card answered the reset, `false` for a present but mute or unpowered card
+	pub powered: bool,
+	/// Complete ATR as uppercase hex, when the card is powered
+	pub atr: Option<String>,
+}
+
+/// Health values from `read_telemetry`, `None` for anything the firmware doesn't report
+#[derive(Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Telemetry {
+	/// Chip temperature in degrees Celsius
+	pub temperature_c: Option<f32>,
+	/// Supply voltage in millivolts
+	pub voltage_mv: Option<u32>,
+	/// Errors the firmware counted since boot
+	pub error_count: Option<u32>,
+}
+
+/// What `reader_capabilities` found out about a reader
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReaderCapabilities {
+	/// Longest command the reader accepts in bytes, `None` when the driver doesn't say
+	pub max_input: Option<u32>,
+	/// `T=0`, `T=1` or `RAW`, `None` when the reader wouldn't report it
+	pub protocol: Option<String>,
+	/// `max_input` leaves room for extended length APDUs
+	pub extended_apdus: Option<bool>,
+}
+
+/// One field where the device config differs from a reference, see `diff_config`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigDifference {
+	/// `AppConfig` field name as serialized (camelCase)
+	pub field: String,
+	/// `null` when the field is unset
+	pub reference_value: serde_json::Value,
+	pub device_value: serde_json::Value,
+}
+
+/// Organisational constraints checked by `validate_against_policy`, unset ones are skipped
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Policy {
+	/// Required VID / PID as hex, compared case insensitively
+	pub vid: Option<String>,
+	pub pid: Option<String>,
+	/// Lowest allowed touch timeout in seconds, a disabled presence check (0) breaks any
+	/// minimum above 0
+	pub min_touch_timeout: Option<u8>,
+	pub max_led_brightness: Option<u8>,
+	pub power_cycle_on_reset: Option<bool>,
+	pub ccid_enabled: Option<bool>,
+	/// Curve names (see `RescueCurves::names`) that must be enabled / disabled
+	#[serde(default)]
+	pub required_curves: Vec<String>,
+	#[serde(default)]
+	pub disabled_curves: Vec<String>,
+}
+
+/// A config value that breaks a `Policy` constraint
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyViolation {
+	/// `Policy` field name (camelCase)
+	pub constraint: String,
+	pub expected: String,
+	pub actual: String,
+}
+
+/// Rescue Applet instructions found by `read_instructions`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstructionSupport {
+	/// Probed instruction bytes the applet didn't reject with `6D00`
+	pub supported: Vec<u8>,
+	/// Instructions that can't be probed without side effects, support is unknown
+	pub unprobed: Vec<u8>,
+}
+
+/// What the connected firmware is known to support, see `read_capabilities`.
+/// Anything that can't be confirmed from the device reads as `false` / `None` / empty.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+	pub firmware: Option<FirmwareVersion>,
+	pub mcu_name: String,
+	/// Firmware is new enough for `write_config` (`MIN_WRITE_FIRMWARE_VERSION`)
+	pub config_writes: bool,
+	/// Secure boot status can be read and the MCU has a known boot key algorithm
+	pub secure_boot: bool,
+	pub secure_boot_algorithm: SecureBootAlgorithm,
+	/// Boot key slots, 0 without secure boot
+	pub secure_boot_key_slots: u8,
+	/// Always `None`, the firmware doesn't report which curves it supports
+	pub supported_curves: Option<Vec<String>>,
+	/// LED drivers that can be selected, empty when the firmware has no `TAG_LED_DRIVER`
+	pub led_drivers: Vec<LedDriver>,
+	/// Longest product name in bytes, without the null terminator
+	pub max_product_name_len: usize,
+	pub pin_required: bool,
+}
@@ -21,6 +21,7 @@ export const LED_DRIVERS = [
   { value: "1", label: "Pico (Standard GPIO)" },
   { value: "2", label: "Pimoroni (RGB)" },
   { value: "3", label: "WS2812 (Neopixel)" },
+  { value: "4", label: "CYW43 (Pico W)" },
   { value: "5", label: "ESP32 Neopixel" },
 ];
 
@@ -35,12 +36,26 @@ export const DEFAULT_CONFIG: DeviceConfig = {
   powerCycleOnReset: false,
   ledSteady: false,
   enableSecp256k1: false,
+  enableSecp256r1: true,
+  enableEd25519: true,
+  enableEd448: false,
   ledDriver: "1",
 };
 
 export const DEFAULT_DEVICE_INFO = {
   serial: "---",
+  serialLabel: "---",
   flashUsed: 0,
   flashTotal: 0,
+  flashFreePercent: 0,
+  lowSpace: false,
+  fileCount: 0,
   firmwareVersion: "---",
+  firmware: null,
+  mcu: 0,
+  mcuName: "---",
+  product: 0,
+  productLabel: "---",
+  bootCount: null,
+  manufactureDate: null,
 };
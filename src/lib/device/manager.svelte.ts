@@ -9,8 +9,18 @@ import type {
   FullDeviceStatus,
   DeviceConfigInput,
   StoredCredential,
+  WriteResult,
 } from "$lib/device/types.svelte";
 
+// Backend errors are plain strings, except status words (`{ type, sw1, sw2, message }`),
+// reader problems (`{ type: "noReader" | "readerBusy", message }`), timeouts
+// (`{ type: "timedOut", started, message }`, re-read the device when `started`) and invalid input
+// (`{ type: "invalidHex" | "valueOutOfRange" | "notAccepted" | "tooLong", field, ..., message }`)
+function errorMessage(err: any): string {
+  if (err && typeof err === "object" && "message" in err) return err.message;
+  return String(err);
+}
+
 class DeviceManager {
   loading = $state(false);
   connected = $state(false);
@@ -52,6 +62,14 @@ class DeviceManager {
 
       this.#originalConfig = JSON.parse(JSON.stringify(this.config));
 
+      for (const warning of status.configWarnings) {
+        logger.add(`Config parse warning: ${warning.message}`, "warning");
+      }
+
+      for (const warning of status.warnings) {
+        logger.add(warning, "warning");
+      }
+
       this.security = {
         secureBoot: status.secureBoot,
         secureLock: status.secureLock,
@@ -68,8 +86,13 @@ class DeviceManager {
       this.connected = true;
     } catch (err) {
       console.error("Connection failed:", err);
-      if (this.connected) {
-        logger.add(`Connection lost: ${err}`, "error");
+      if (err && typeof err === "object" && (err as any).type === "notInRescueMode") {
+        logger.add(`${errorMessage(err)} Reconnect the device in rescue mode to configure it.`, "warning");
+      } else if (err && typeof err === "object" && (err as any).type === "unknownCard") {
+        // The message includes the ATR, worth keeping for support requests
+        logger.add(errorMessage(err), "warning");
+      } else if (this.connected) {
+        logger.add(`Connection lost: ${errorMessage(err)}`, "error");
       }
       this.connected = false;
     } finally {
@@ -113,16 +136,20 @@ class DeviceManager {
         logger.add(`Queuing change: Timeout -> ${this.config.touchTimeout}`, "info");
       }
 
-      const optionsChanged =
-        this.config.ledDimmable !== this.#originalConfig.ledDimmable ||
-        this.config.powerCycleOnReset !== this.#originalConfig.powerCycleOnReset ||
-        this.config.ledSteady !== this.#originalConfig.ledSteady;
-
-      if (optionsChanged) {
+      // Option flags are merged into the device bitmask one by one, only send the changed ones
+      if (this.config.ledDimmable !== this.#originalConfig.ledDimmable) {
         rustConfig.ledDimmable = this.config.ledDimmable;
+        logger.add(`Queuing change: LED Dimmable -> ${this.config.ledDimmable}`, "info");
+      }
+
+      if (this.config.powerCycleOnReset !== this.#originalConfig.powerCycleOnReset) {
         rustConfig.powerCycleOnReset = this.config.powerCycleOnReset;
+        logger.add(`Queuing change: Power Cycle on Reset -> ${this.config.powerCycleOnReset}`, "info");
+      }
+
+      if (this.config.ledSteady !== this.#originalConfig.ledSteady) {
         rustConfig.ledSteady = this.config.ledSteady;
-        logger.add("Queuing change: Device Options (Bitmask updated)", "info");
+        logger.add(`Queuing change: LED Steady -> ${this.config.ledSteady}`, "info");
       }
 
       if (this.config.enableSecp256k1 !== this.#originalConfig.enableSecp256k1) {
@@ -140,15 +167,15 @@ class DeviceManager {
         return { success: false, msg: "No changes detected." };
       } else {
         logger.add("Sending configuration to device...", "info");
-        const response = await invoke("write_config", { config: rustConfig });
-        logger.add(`Device Response: ${response}`, "success");
+        const response = await invoke<WriteResult>("write_config", { config: rustConfig });
+        logger.add(`Device Response: ${response.message}`, "success");
 
         await this.refresh();
         return { success: true, msg: "Configuration Applied Successfully!" };
       }
     } catch (err: any) {
-      logger.add(`Write Failed: ${err}`, "error");
-      return { success: false, msg: `Error: ${err}` };
+      logger.add(`Write Failed: ${errorMessage(err)}`, "error");
+      return { success: false, msg: `Error: ${errorMessage(err)}` };
     } finally {
       this.loading = false;
     }
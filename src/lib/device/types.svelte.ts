@@ -9,7 +9,14 @@ export interface DeviceConfig {
   powerCycleOnReset: boolean;
   ledSteady: boolean;
   enableSecp256k1: boolean;
+  enableSecp256r1: boolean;
+  enableEd25519: boolean;
+  enableEd448: boolean;
   ledDriver: string;
+  // Diagnostic, complete TAG_OPTS / TAG_CURVES values as read from the device
+  optsRaw?: number;
+  curvesRaw?: number;
+  ccidEnabled?: boolean;
 }
 
 export interface DeviceConfigInput {
@@ -17,27 +24,68 @@ export interface DeviceConfigInput {
   pid?: string;
   productName?: string;
   ledGpio?: number;
+  ledPinLabel?: string;
   ledBrightness?: number;
   touchTimeout?: number;
+  allowTouchDisable?: boolean;
   ledDriver?: number;
   ledDimmable?: boolean;
   powerCycleOnReset?: boolean;
   ledSteady?: boolean;
   enableSecp256k1?: boolean;
+  enableSecp256r1?: boolean;
+  enableEd25519?: boolean;
+  enableEd448?: boolean;
+}
+
+export interface FirmwareVersion {
+  major: number;
+  minor: number;
 }
 
 export interface DeviceInfo {
   serial: string;
+  serialLabel: string;
   flashUsed: number;
   flashTotal: number;
+  flashFreePercent: number;
+  lowSpace: boolean;
+  fileCount: number;
   firmwareVersion: string;
+  firmware: FirmwareVersion | null;
+  mcu: number;
+  mcuName: string;
+  product: number;
+  productLabel: string;
+  bootCount: number | null;
+  manufactureDate: string | null;
+}
+
+export interface ParseWarning {
+  tag: number;
+  message: string;
 }
 
 export interface FullDeviceStatus {
   info: DeviceInfo;
   config: DeviceConfig;
+  configWarnings: ParseWarning[];
   secureBoot: boolean;
   secureLock: boolean;
+  secureBootAlgorithm: "ecdsaSecp256k1" | "unknown";
+  bootloaderVersion: FirmwareVersion | null;
+  secureLockAttempts: number | null;
+  enabledCurves: string[];
+  supportedCurves: string[] | null;
+  pinRequired: boolean;
+  warnings: string[];
+}
+
+export interface WriteResult {
+  applied: boolean;
+  tagsWritten: number[];
+  verified: boolean;
+  message: string;
 }
 
 export interface SecurityState {